@@ -17,14 +17,22 @@ pub enum Command {
 ///
 /// This command creates and updates such a feature aspect across the crate graph.
 ///
+/// Instead of specifying a single aspect on the command line, any number of aspects can be
+/// declared in the `[workspace.metadata.feature-aspect]` table of the workspace root manifest, and
+/// they will all be processed when no `--name` or `--leaf-feature` is given.
+///
 /// See the documentation in the repository for more usage examples:
 /// https://github.com/dflemstr/cargo-feature-aspect
 #[derive(Debug, clap::Args)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct FeatureAspectArgs {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
     /// The name of the resulting feature aspect.
     ///
-    /// This will be inferred from the name of the leaf feature if there's only one, but it might
-    /// be useful to specify this flag if there are multiple leaf features.
+    /// This will be inferred from the name of the leaf features if they all have the same one, but
+    /// it might be useful to specify this flag if they don't.
     #[arg(short, long)]
     pub name: Option<String>,
 
@@ -32,22 +40,77 @@ pub struct FeatureAspectArgs {
     /// `logging/enable-tracing` to only match a specific crate.
     ///
     /// These are the features that identify "root crates" that should have their features spread to
-    /// all dependee crates.
+    /// all dependee crates, or to all dependency crates with `--direction downward`.
+    ///
+    /// Package and feature names can be globs like `backend-*/enable-tracing`, or regular
+    /// expressions with a `re:` prefix like `re:backend-(api|db)/re:tracing-.+`, which need to
+    /// match the whole name.
     #[arg(short = 'f', long = "leaf-feature")]
     pub leaf_features: Vec<String>,
 
     /// Add extra elements to the generated feature.  For example, it might be useful to add a
     /// `dep:logging` to all features that have the `enable-tracing` feature from the example above.
+    ///
+    /// The placeholders `{package}` and `{feature}` are replaced by the name of the package and
+    /// of its aspect feature, and params containing `{dep}` are added once for each in-scope
+    /// dependency, e.g. `{dep}?/serde`.
+    ///
+    /// A param can be made conditional with ` if ` followed by comma-separated conditions that all
+    /// need to hold: `dep(x)`, `optional(x)` or `required(x)` for dependencies, `feature(f)` for
+    /// local features and `target(kind)` for target kinds like `proc-macro`, each optionally
+    /// negated with `!`.  A `dep:x` param implicitly has the condition `dep(x)`.
     #[arg(short, long = "add-feature-param")]
     pub add_feature_params: Vec<String>,
 
-    /// Do not modify `Cargo.toml` files, instead print the changes that would be made.
-    #[arg(short, long)]
-    pub dry_run: bool,
+    /// The kinds of dependencies that propagate the feature aspect.
+    ///
+    /// Cargo does not allow features to enable features of dev-dependencies, so by default only
+    /// normal and build dependencies are considered.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub dependency_kinds: Vec<DependencyKind>,
 
-    /// Do not modify `Cargo.toml` files, instead fail the command if changes would be made.
-    #[arg(short, long)]
-    pub verify: bool,
+    /// The direction in which the feature aspect is propagated.
+    #[arg(long, value_enum)]
+    pub direction: Option<Direction>,
+
+    /// Ensure that the `default` feature of in-scope packages includes the feature aspect, or that
+    /// it excludes it.
+    #[arg(long, value_enum)]
+    pub default_feature: Option<DefaultFeature>,
+
+    /// Stop propagating the feature aspect at a package, e.g. `xtask`, or at a single dependency
+    /// edge, e.g. `api:ffi` for the `ffi` dependency of `api`.
+    ///
+    /// A stopped package never gets the feature aspect, and neither do packages that only depend
+    /// on in-scope packages through it.  Packages can also declare `stop = true` or
+    /// `stop-dependencies = [...]` in their `[package.metadata.feature-aspect]` table.
+    #[arg(long)]
+    pub stop_at: Vec<String>,
+
+    /// Only process the named feature aspect from `[workspace.metadata.feature-aspect]`, instead
+    /// of all of them.  May be specified multiple times.
+    #[arg(long = "aspect", conflicts_with_all = ["name", "leaf_features", "add_feature_params"])]
+    pub aspects: Vec<String>,
+
+    /// Remove the feature aspect from packages that are no longer in scope.
+    ///
+    /// As a safety measure, the feature is only removed if all of its params look like they were
+    /// generated for the feature aspect, and if no other feature of the package refers to it.
+    #[arg(long)]
+    pub prune: bool,
+
+    /// Instead of editing manifests, print the in-scope dependency graph of each feature aspect to
+    /// stdout, like a dry run.
+    ///
+    /// Packages with leaf features are highlighted, packages that would be changed are marked with
+    /// a bold border, packages outside of the workspace are dashed, and edges are labelled with
+    /// the params that forward the aspect, e.g. `foo/x` or `foo?/x`.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        conflicts_with_all = ["verify", "diff", "message_format", "package", "workspace", "all", "exclude"]
+    )]
+    pub graph: Option<GraphFormat>,
 
     /// Do not sort params the feature spec lexicographically.  If specified, new features are added
     /// to the end instead.
@@ -57,6 +120,150 @@ pub struct FeatureAspectArgs {
     #[arg(long)]
     pub no_sort: bool,
 
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Action {
+    Remove(RemoveArgs),
+    Rename(RenameArgs),
+    Suggest(SuggestArgs),
+    Why(WhyArgs),
+}
+
+/// Removes a feature aspect from the whole workspace.
+///
+/// The aspect feature is deleted from every workspace member, and all references to it are
+/// stripped from other features, i.e. `enable-tracing` as well as `foo/enable-tracing` and
+/// `foo?/enable-tracing` for workspace dependencies `foo`.  Packages that override the name of the
+/// aspect feature in `[package.metadata.feature-aspect]` are referred to by that name.
+///
+/// Leaf features are kept, since code is gated on them, unless `--remove-leaf-features` is passed.
+#[derive(Debug, clap::Args)]
+pub struct RemoveArgs {
+    /// The name of the feature aspect to remove.
+    #[arg(short, long)]
+    pub name: String,
+
+    /// The leaf features of the feature aspect, if it is not declared in the workspace metadata.
+    ///
+    /// The leaf features must be known in order to keep them, unless `--remove-leaf-features` is
+    /// passed.
+    #[arg(short = 'f', long = "leaf-feature")]
+    pub leaf_features: Vec<String>,
+
+    /// Also remove the aspect feature from packages where it is a leaf feature.
+    #[arg(long)]
+    pub remove_leaf_features: bool,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Renames a feature aspect in the whole workspace.
+///
+/// The aspect feature is renamed in every workspace member, keeping its position in `[features]`
+/// as well as any comments, and all references to it are updated, i.e. `enable-tracing` as well as
+/// `foo/enable-tracing` and `foo?/enable-tracing` for workspace dependencies `foo`.
+///
+/// Packages that override the name of the aspect feature in `[package.metadata.feature-aspect]`
+/// keep their name, and only the override is updated to refer to the new name of the aspect.  If
+/// the feature aspect is declared in `[workspace.metadata.feature-aspect]`, its key or `name` and
+/// its leaf features with the old name are updated as well.
+#[derive(Debug, clap::Args)]
+pub struct RenameArgs {
+    /// The current name of the feature aspect.
+    #[arg(long)]
+    pub from: String,
+
+    /// The new name of the feature aspect.
+    #[arg(long)]
+    pub to: String,
+
+    /// Keep the old feature name as a deprecated alias for the new one, e.g.
+    /// `enable-tracing = ["tracing"]`, in packages that can be published, so that users of those
+    /// packages don't break.
+    #[arg(long)]
+    pub alias: bool,
+
+    #[command(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Suggests feature aspects for feature propagation chains that already exist in the workspace.
+///
+/// Features that exist in several workspace members and are forwarded to dependencies with the
+/// same name are reported along with their leaf crates, how many of the crates that would be in
+/// scope already propagate them, and gaps where the chain is broken.  The matching entries for
+/// `[workspace.metadata.feature-aspect]` are printed to stdout.
+#[derive(Debug, clap::Args)]
+pub struct SuggestArgs {
+    #[command(flatten)]
+    pub metadata: MetadataArgs,
+}
+
+/// Explains why a package is in scope for a feature aspect.
+///
+/// Prints the shortest dependency path from the package to a package with a leaf feature, e.g.
+/// `api -> storage -> logging[enable-tracing]`, or from a root package for downward aspects, and
+/// the params that each in-scope dependency contributes to the aspect feature of the package.
+#[derive(Debug, clap::Args)]
+pub struct WhyArgs {
+    /// The workspace member to explain.
+    pub package: String,
+
+    /// The name of the feature aspect, as declared in `[workspace.metadata.feature-aspect]`.
+    #[arg(short, long)]
+    pub name: String,
+
+    /// The leaf features of the feature aspect, if it is not declared in the workspace metadata.
+    #[arg(short = 'f', long = "leaf-feature")]
+    pub leaf_features: Vec<String>,
+
+    /// The direction of the feature aspect, if it is not declared in the workspace metadata.
+    #[arg(long, value_enum, requires = "leaf_features")]
+    pub direction: Option<Direction>,
+
+    #[command(flatten)]
+    pub metadata: MetadataArgs,
+}
+
+/// Arguments shared by all the ways of running the command.
+#[derive(Debug, clap::Args)]
+pub struct CommonArgs {
+    /// Do not modify `Cargo.toml` files, instead print the changes that would be made.
+    #[arg(short, long)]
+    pub dry_run: bool,
+
+    /// Do not modify `Cargo.toml` files, instead fail the command if changes would be made.
+    #[arg(short, long)]
+    pub verify: bool,
+
+    /// Print a unified diff of each `Cargo.toml` file that is (or would be) changed.
+    #[arg(long)]
+    pub diff: bool,
+
+    /// The format of the reported changes.  With `json`, one JSON object per change is printed to
+    /// stdout, followed by a summary object, while the usual messages still go to stderr.
+    ///
+    /// With `github` or `sarif`, each change is reported as an annotation on the line of the
+    /// feature or param in its `Cargo.toml`, which is an error with `--verify`.
+    #[arg(long, value_name = "FMT", default_value = "human")]
+    pub message_format: MessageFormat,
+
+    // Only the selected workspace members are edited or verified, while the scope of a feature
+    // aspect is still computed over the whole workspace.  All members are selected by default.
+    #[command(flatten)]
+    pub workspace: clap_cargo::Workspace,
+
+    #[command(flatten)]
+    pub metadata: MetadataArgs,
+}
+
+/// Arguments for resolving the workspace metadata.
+#[derive(Debug, clap::Args)]
+pub struct MetadataArgs {
     #[command(flatten)]
     pub manifest: clap_cargo::Manifest,
 
@@ -69,6 +276,77 @@ pub struct FeatureAspectArgs {
     pub locked: bool,
 }
 
+/// The direction in which a feature aspect is propagated through the dependency graph.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// From the crates with leaf features to all of their dependees, e.g. `enable-tracing`.
+    #[default]
+    Upward,
+    /// From the crates with leaf features, acting as roots, to all of their workspace
+    /// dependencies, e.g. `std`.
+    Downward,
+}
+
+/// How a feature aspect relates to the `default` feature of in-scope packages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DefaultFeature {
+    /// The aspect is enabled by default, e.g. `default = ["std"]`.
+    Include,
+    /// The aspect is never enabled by default.
+    Exclude,
+}
+
+/// A format for reporting changes to manifests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Human-readable messages on stderr
+    #[default]
+    Human,
+    /// JSON objects on stdout, in addition to the human-readable messages
+    Json,
+    /// GitHub Actions workflow commands on stdout, which annotate the manifests
+    Github,
+    /// A SARIF log on stdout, e.g. for uploading to GitHub code scanning
+    Sarif,
+}
+
+/// A format for printing the dependency graph of a feature aspect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
+/// A kind of dependency, corresponding to a dependency section of the manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    /// `[dependencies]`
+    Normal,
+    /// `[build-dependencies]`
+    Build,
+    /// `[dev-dependencies]`
+    Dev,
+}
+
+impl DependencyKind {
+    /// The kinds of dependencies used when none are specified.
+    pub const DEFAULT: &'static [Self] = &[Self::Normal, Self::Build];
+
+    pub fn matches(self, kind: cargo_metadata::DependencyKind) -> bool {
+        matches!(
+            (self, kind),
+            (Self::Normal, cargo_metadata::DependencyKind::Normal)
+                | (Self::Build, cargo_metadata::DependencyKind::Build)
+                | (Self::Dev, cargo_metadata::DependencyKind::Development)
+        )
+    }
+}
+
 #[test]
 fn verify_cli() {
     use clap::CommandFactory as _;
@@ -0,0 +1,88 @@
+use crate::cli;
+use std::collections;
+
+/// The key under `[workspace.metadata]` and `[package.metadata]` that holds our configuration.
+pub const METADATA_KEY: &str = "feature-aspect";
+
+/// A feature aspect declared in the workspace root manifest, e.g.:
+///
+/// ```toml
+/// [workspace.metadata.feature-aspect.enable-tracing]
+/// leaf-features = ["logging/enable-tracing"]
+/// add-feature-params = ["dep:logging"]
+/// ```
+///
+/// The fields mirror the flags of the same name on the command line.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AspectConfig {
+    /// The name of the resulting feature; defaults to the key of the aspect table.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub leaf_features: Vec<String>,
+    #[serde(default)]
+    pub add_feature_params: Vec<String>,
+    #[serde(default)]
+    pub no_sort: bool,
+    #[serde(default)]
+    pub prune: bool,
+    #[serde(default)]
+    pub dependency_kinds: Vec<cli::DependencyKind>,
+    #[serde(default)]
+    pub stop_at: Vec<String>,
+    pub direction: Option<cli::Direction>,
+    pub default_feature: Option<cli::DefaultFeature>,
+}
+
+/// Settings of a single package that apply to all feature aspects, declared in its own manifest,
+/// e.g.:
+///
+/// ```toml
+/// [package.metadata.feature-aspect]
+/// stop-dependencies = ["ffi-sys"]
+/// feature-names = { enable-tracing = "trace" }
+/// ```
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PackageConfig {
+    /// Never add feature aspects to this package, nor to packages that only depend on in-scope
+    /// packages through it.
+    #[serde(default)]
+    pub stop: bool,
+    /// Dependencies, by package or local name, that feature aspects are not propagated through.
+    #[serde(default)]
+    pub stop_dependencies: Vec<String>,
+    /// The names that feature aspects have in this package, keyed by the name of the aspect, for
+    /// packages that can't use the common name, e.g. `enable-tracing = "trace"`.
+    #[serde(default)]
+    pub feature_names: collections::BTreeMap<String, String>,
+}
+
+/// Reads all feature aspects declared in `[workspace.metadata.feature-aspect]`, keyed by aspect
+/// name.
+pub fn workspace_aspects(
+    metadata: &cargo_metadata::Metadata,
+) -> anyhow::Result<collections::BTreeMap<String, AspectConfig>> {
+    use anyhow::Context as _;
+
+    match metadata.workspace_metadata.get(METADATA_KEY) {
+        Some(value) => serde_json::from_value(value.clone())
+            .with_context(|| format!("invalid `[workspace.metadata.{METADATA_KEY}]` table")),
+        None => Ok(collections::BTreeMap::new()),
+    }
+}
+
+/// Reads the settings in `[package.metadata.feature-aspect]` of a package.
+pub fn package_config(package: &cargo_metadata::Package) -> anyhow::Result<PackageConfig> {
+    use anyhow::Context as _;
+
+    match package.metadata.get(METADATA_KEY) {
+        Some(value) => serde_json::from_value(value.clone()).with_context(|| {
+            format!(
+                "invalid `[package.metadata.{METADATA_KEY}]` table in package {}",
+                package.name
+            )
+        }),
+        None => Ok(PackageConfig::default()),
+    }
+}
@@ -1,69 +1,443 @@
-use crate::cli;
+use crate::{cli, config, metadata, param, pattern};
 use std::{borrow, collections};
 
 pub struct Context<'a> {
     pub feature_name: borrow::Cow<'a, str>,
-    pub extra_feature_params: Vec<&'a str>,
-    pub dry_run: bool,
-    pub verify: bool,
+    /// Extra params for the aspect feature, which may contain placeholders and conditions, see
+    /// [`Context::expand_extra_feature_params`].
+    pub extra_feature_params: Vec<param::ExtraParam<'a>>,
     pub sort: bool,
-    pub has_changes: bool,
-    pub unqualified_leaf_features: Vec<&'a str>,
-    pub qualified_leaf_features: Vec<(&'a str, &'a str)>,
-    pub in_scope_packages: collections::HashSet<&'a str>,
+    pub prune: bool,
+    pub direction: cli::Direction,
+    pub default_feature: Option<cli::DefaultFeature>,
+    pub dependency_kinds: &'a [cli::DependencyKind],
+    pub unqualified_leaf_features: Vec<pattern::Pattern<'a>>,
+    pub qualified_leaf_features: Vec<(pattern::Pattern<'a>, pattern::Pattern<'a>)>,
+    /// Packages that the feature aspect never propagates to or through.
+    pub stop_packages: Vec<&'a str>,
+    /// Dependency edges, as `(package, dependency)`, that the feature aspect is not propagated
+    /// through.
+    pub stop_dependencies: Vec<(&'a str, &'a str)>,
+    pub in_scope_packages: collections::HashSet<&'a cargo_metadata::PackageId>,
+    /// The matching leaf features of in-scope packages that are not workspace members.  Since we
+    /// can't add the aspect feature to those, dependees forward the leaf features directly.
+    pub external_leaf_features: collections::HashMap<&'a cargo_metadata::PackageId, Vec<&'a str>>,
 }
 
 impl<'a> Context<'a> {
-    pub fn new(args: &'a cli::FeatureAspectArgs) -> anyhow::Result<Self> {
-        let feature_name = if let Some(name) = &args.name {
-            name.into()
-        } else if let &[name] = &args.leaf_features.as_slice() {
-            // We have exactly one leaf feature, see if it is scoped by package
-            if let Some((_, feature)) = name.split_once('/') {
-                feature.into()
-            } else {
-                name.into()
-            }
-        } else {
-            anyhow::bail!("Must specify specify --name  or else specify exactly one --leaf-feature")
+    /// Creates a context for the feature aspect that is fully specified on the command line.
+    ///
+    /// If there's no `--name`, it is inferred from the leaf features, which is why the workspace
+    /// is needed.
+    pub fn new(args: &'a cli::FeatureAspectArgs, ws: &metadata::Workspace) -> anyhow::Result<Self> {
+        let mut ctx = Self {
+            sort: !args.no_sort,
+            prune: args.prune,
+            direction: args.direction.unwrap_or_default(),
+            default_feature: args.default_feature,
+            ..Self::with_params(
+                "".into(),
+                &args.leaf_features,
+                &args.add_feature_params,
+                &args.dependency_kinds,
+                &args.stop_at,
+            )?
+        };
+
+        ctx.feature_name = match &args.name {
+            Some(name) => name.into(),
+            None => ctx.infer_feature_name(ws)?.into(),
+        };
+
+        Ok(ctx)
+    }
+
+    /// Creates a context for a feature aspect declared in the workspace metadata, with settings
+    /// from the command line taking precedence if given.
+    pub fn from_config(
+        key: &'a str,
+        aspect: &'a config::AspectConfig,
+        args: Option<&'a cli::FeatureAspectArgs>,
+    ) -> anyhow::Result<Self> {
+        if aspect.leaf_features.is_empty() {
+            anyhow::bail!(
+                "feature aspect {key:?} in the workspace metadata has no `leaf-features`"
+            );
+        }
+
+        let feature_name = aspect.name.as_deref().unwrap_or(key).into();
+        let dependency_kinds = match args {
+            Some(args) if !args.dependency_kinds.is_empty() => &args.dependency_kinds,
+            _ => &aspect.dependency_kinds,
         };
 
-        let extra_feature_params = args.add_feature_params.iter().map(String::as_str).collect();
-        let dry_run = args.dry_run;
-        let verify = args.verify;
-        let sort = !args.no_sort;
-        let has_changes = false;
+        Ok(Self {
+            sort: !(args.is_some_and(|a| a.no_sort) || aspect.no_sort),
+            prune: args.is_some_and(|a| a.prune) || aspect.prune,
+            direction: args
+                .and_then(|a| a.direction)
+                .or(aspect.direction)
+                .unwrap_or_default(),
+            default_feature: args
+                .and_then(|a| a.default_feature)
+                .or(aspect.default_feature),
+            ..Self::with_params(
+                feature_name,
+                &aspect.leaf_features,
+                &aspect.add_feature_params,
+                dependency_kinds,
+                args.into_iter()
+                    .flat_map(|a| &a.stop_at)
+                    .chain(&aspect.stop_at),
+            )?
+        })
+    }
+
+    /// Creates a context for an ad-hoc feature aspect with otherwise default settings.
+    pub fn from_leaf_features(
+        name: &'a str,
+        leaf_features: &'a [String],
+        direction: cli::Direction,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            direction,
+            ..Self::with_params(name.into(), leaf_features, &[], &[], [])?
+        })
+    }
+
+    fn with_params(
+        feature_name: borrow::Cow<'a, str>,
+        leaf_features: &'a [String],
+        add_feature_params: &'a [String],
+        dependency_kinds: &'a [cli::DependencyKind],
+        stop_at: impl IntoIterator<Item = &'a String>,
+    ) -> anyhow::Result<Self> {
+        let dependency_kinds = if dependency_kinds.is_empty() {
+            cli::DependencyKind::DEFAULT
+        } else {
+            dependency_kinds
+        };
+        let extra_feature_params = add_feature_params
+            .iter()
+            .map(|p| param::ExtraParam::parse(p))
+            .collect::<anyhow::Result<_>>()?;
 
         // We expect these to be tiny, so it's overkill to use a hash data structure
         let mut unqualified_leaf_features = Vec::new();
         let mut qualified_leaf_features = Vec::new();
 
-        for leaf_feature in &args.leaf_features {
+        for leaf_feature in leaf_features {
             if let Some((pkg, feature)) = leaf_feature.split_once('/') {
-                if !qualified_leaf_features.contains(&(pkg, feature)) {
-                    qualified_leaf_features.push((pkg, feature));
+                let pair = (
+                    pattern::Pattern::parse(pkg)?,
+                    pattern::Pattern::parse(feature)?,
+                );
+                if !qualified_leaf_features.contains(&pair) {
+                    qualified_leaf_features.push(pair);
                 }
             } else {
-                let feature = leaf_feature.as_str();
+                let feature = pattern::Pattern::parse(leaf_feature)?;
                 if !unqualified_leaf_features.contains(&feature) {
                     unqualified_leaf_features.push(feature);
                 }
             }
         }
 
+        let mut stop_packages = Vec::new();
+        let mut stop_dependencies = Vec::new();
+
+        for stop_point in stop_at {
+            if let Some((pkg, dep)) = stop_point.split_once(':') {
+                stop_dependencies.push((pkg, dep));
+            } else {
+                stop_packages.push(stop_point.as_str());
+            }
+        }
+
         // This might have relatively many elems so might make sense to hash values here
         let in_scope_packages = collections::HashSet::new();
+        let external_leaf_features = collections::HashMap::new();
 
         Ok(Self {
             feature_name,
             extra_feature_params,
-            dry_run,
-            verify,
-            sort,
-            has_changes,
+            sort: true,
+            prune: false,
+            direction: cli::Direction::default(),
+            default_feature: None,
+            dependency_kinds,
             unqualified_leaf_features,
             qualified_leaf_features,
+            stop_packages,
+            stop_dependencies,
             in_scope_packages,
+            external_leaf_features,
+        })
+    }
+
+    /// The dependencies of a package that propagate the feature aspect, according to the selected
+    /// dependency kinds and stop points.
+    ///
+    /// Note that the same dependency might be returned several times if it is listed in several
+    /// dependency sections of the manifest.
+    pub fn dependencies<'p>(
+        &'p self,
+        ws: &'p metadata::Workspace,
+        package: &'p cargo_metadata::Package,
+    ) -> impl Iterator<Item = &'p cargo_metadata::Dependency> + 'p {
+        let stop_dependencies = ws
+            .member_config(&package.id)
+            .map_or(&[][..], |c| c.stop_dependencies.as_slice());
+
+        package.dependencies.iter().filter(move |dep| {
+            let is_stopped = |name: &str| {
+                stop_dependencies.iter().any(|d| d == name)
+                    || self
+                        .stop_dependencies
+                        .contains(&(package.name.as_str(), name))
+            };
+
+            self.dependency_kinds.iter().any(|k| k.matches(dep.kind))
+                && !is_stopped(&dep.name)
+                && !is_stopped(metadata::local_name(dep))
         })
     }
+
+    /// Whether the feature aspect must never be added to a package, whether because of
+    /// `--stop-at` or its own `[package.metadata.feature-aspect]` table.
+    pub fn is_stopped(&self, ws: &metadata::Workspace, package: &cargo_metadata::Package) -> bool {
+        self.stop_packages.contains(&package.name.as_str())
+            || ws.member_config(&package.id).is_some_and(|c| c.stop)
+    }
+
+    /// Infers the name of the feature aspect from the leaf features, which is only possible if
+    /// all of them, or all of the features they match in the workspace, have the same name.
+    fn infer_feature_name(&self, ws: &metadata::Workspace) -> anyhow::Result<String> {
+        let mut names = collections::BTreeSet::new();
+        let mut has_patterns = false;
+
+        for feature in self
+            .unqualified_leaf_features
+            .iter()
+            .chain(self.qualified_leaf_features.iter().map(|(_, f)| f))
+        {
+            match feature.as_exact() {
+                Some(name) => {
+                    names.insert(name);
+                }
+                None => has_patterns = true,
+            }
+        }
+
+        if has_patterns {
+            for package in ws.members.iter().chain(&ws.externals) {
+                names.extend(self.leaf_features(package));
+            }
+        }
+
+        match names.into_iter().collect::<Vec<_>>().as_slice() {
+            [name] => Ok((*name).to_owned()),
+            names => anyhow::bail!(
+                "Must specify --name, or else specify leaf features that all match the same feature name, but they match {names:?}"
+            ),
+        }
+    }
+
+    fn is_leaf_feature(&self, package_name: &str, feature: &str) -> bool {
+        self.unqualified_leaf_features
+            .iter()
+            .any(|f| f.matches(feature))
+            || self
+                .qualified_leaf_features
+                .iter()
+                .any(|(p, f)| p.matches(package_name) && f.matches(feature))
+    }
+
+    /// The features of a package that match any of the leaf features.
+    pub fn leaf_features<'p>(&self, package: &'p cargo_metadata::Package) -> Vec<&'p str> {
+        // Here we do lots of linear searches but since these are small vecs, it is not worth it
+        // to do some fancy hash set stuff, since hashing all the strings will probably take more
+        // time than just traversing the vec.
+        package
+            .features
+            .keys()
+            .map(String::as_str)
+            .filter(|&feature| self.is_leaf_feature(&package.name, feature))
+            .collect()
+    }
+
+    /// The name of the aspect feature in a workspace member, which is the name of the feature
+    /// aspect unless the member overrides it in its `[package.metadata.feature-aspect]` table.
+    pub fn local_feature_name<'p>(
+        &'p self,
+        ws: &'p metadata::Workspace,
+        id: &cargo_metadata::PackageId,
+    ) -> &'p str {
+        ws.member_config(id)
+            .and_then(|c| c.feature_names.get(self.feature_name.as_ref()))
+            .map_or(self.feature_name.as_ref(), String::as_str)
+    }
+
+    /// Whether `dep/dep_feature` forwards the feature aspect from a package to its dependency with
+    /// the local name `dep`, i.e. to the aspect feature of a workspace member, or to a leaf feature
+    /// of an external package.
+    ///
+    /// External packages might legitimately have a feature with the same name as the feature
+    /// aspect that we don't control, so forwarding to it is only ours if it is a leaf feature.
+    fn forwards_aspect(
+        &self,
+        ws: &metadata::Workspace,
+        package: &cargo_metadata::Package,
+        dep: &str,
+        dep_feature: &str,
+    ) -> bool {
+        package
+            .dependencies
+            .iter()
+            .filter(|d| metadata::local_name(d) == dep)
+            .any(|d| match ws.resolve_member(d) {
+                // The common name is still considered ours, so that params are replaced when a
+                // dependency starts overriding the name
+                Some(id) => {
+                    dep_feature == self.feature_name
+                        || dep_feature == self.local_feature_name(ws, id)
+                }
+                None => {
+                    let dep_package = ws
+                        .resolve_dependency(package, d)
+                        .and_then(|id| ws.external(id));
+                    let dep_name = dep_package.map_or(d.name.as_str(), |p| p.name.as_str());
+                    self.is_leaf_feature(dep_name, dep_feature)
+                }
+            })
+    }
+
+    /// Whether a feature param of a package forwards the feature aspect to a dependency, i.e.
+    /// `dep/feature` or `dep?/feature`.  Such params are managed by us and get removed when they
+    /// become stale.
+    pub fn is_dependency_param(
+        &self,
+        ws: &metadata::Workspace,
+        package: &cargo_metadata::Package,
+        param: &str,
+    ) -> bool {
+        let Some((dep, dep_feature)) = param.split_once('/') else {
+            return false;
+        };
+        let dep = dep.strip_suffix('?').unwrap_or(dep);
+
+        self.forwards_aspect(ws, package, dep, dep_feature)
+    }
+
+    /// Whether a feature param of a package looks like one that could have been generated for the
+    /// feature aspect.
+    pub fn is_generated_param(
+        &self,
+        ws: &metadata::Workspace,
+        package: &cargo_metadata::Package,
+        param: &str,
+    ) -> bool {
+        let is_leaf_feature = |feature: &str| {
+            self.unqualified_leaf_features
+                .iter()
+                .chain(self.qualified_leaf_features.iter().map(|(_, f)| f))
+                .any(|f| f.matches(feature))
+        };
+
+        let dep_names: Vec<&str> = package
+            .dependencies
+            .iter()
+            .map(metadata::local_name)
+            .collect();
+
+        // Conditions might have changed since the param was generated, so ignore them here
+        if self
+            .expand_extra_feature_params(ws, package, &dep_names)
+            .iter()
+            .any(|(p, _)| p == param)
+        {
+            true
+        } else if let Some((dep, dep_feature)) = param.split_once('/') {
+            let dep = dep.strip_suffix('?').unwrap_or(dep);
+            self.forwards_aspect(ws, package, dep, dep_feature)
+        } else {
+            is_leaf_feature(param)
+        }
+    }
+
+    /// Expands the placeholders in the extra feature params for a package: `{package}` is replaced
+    /// by the package name, `{feature}` by the name of the aspect feature in the package, and
+    /// params containing `{dep}` are expanded once for each of the given dependency names.
+    ///
+    /// Returns the expanded params along with whether their conditions hold for the package.
+    pub fn expand_extra_feature_params(
+        &self,
+        ws: &metadata::Workspace,
+        package: &cargo_metadata::Package,
+        dep_names: &[&str],
+    ) -> Vec<(String, bool)> {
+        let feature = self.local_feature_name(ws, &package.id);
+        let mut params = Vec::new();
+
+        for extra_param in &self.extra_feature_params {
+            let deps: Vec<Option<&str>> = if extra_param.is_per_dependency() {
+                dep_names.iter().copied().map(Some).collect()
+            } else {
+                vec![None]
+            };
+
+            for dep in deps {
+                let expand = |s| param::expand(s, &package.name, feature, dep);
+                let holds = extra_param.conditions.iter().all(|condition| {
+                    Self::condition_holds(package, condition, &expand(condition.arg))
+                });
+                params.push((expand(extra_param.template), holds));
+            }
+        }
+
+        params
+    }
+
+    /// Whether a condition of an extra param, with the placeholders of its argument already
+    /// expanded, holds for a package.
+    ///
+    /// Dependency conditions look at all dependencies of the package, regardless of whether they
+    /// propagate the feature aspect.
+    fn condition_holds(
+        package: &cargo_metadata::Package,
+        condition: &param::Condition,
+        arg: &str,
+    ) -> bool {
+        // Merge the entries of the dependency in all of its sections, like `describe_changes`
+        let mut optional = None;
+        for dep in &package.dependencies {
+            if metadata::local_name(dep) == arg {
+                *optional.get_or_insert(true) &= dep.optional;
+            }
+        }
+
+        let holds = match condition.kind {
+            param::ConditionKind::Dep => optional.is_some(),
+            param::ConditionKind::Optional => optional == Some(true),
+            param::ConditionKind::Required => optional == Some(false),
+            param::ConditionKind::Feature => package.features.contains_key(arg),
+            param::ConditionKind::Target => package
+                .targets
+                .iter()
+                .any(|t| t.kind.iter().any(|k| k == arg)),
+        };
+
+        holds != condition.negated
+    }
+
+    /// Returns the package that a dependency refers to, if it is in scope for the feature aspect.
+    pub fn in_scope_dependency<'w>(
+        &self,
+        ws: &'w metadata::Workspace,
+        package: &cargo_metadata::Package,
+        dependency: &cargo_metadata::Dependency,
+    ) -> Option<&'w cargo_metadata::PackageId> {
+        ws.resolve_dependency(package, dependency)
+            .filter(|id| self.in_scope_packages.contains(id))
+    }
 }
@@ -0,0 +1,254 @@
+use crate::output;
+
+/// The number of unchanged lines to show around each change.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    /// The line at the given index of the old text is unchanged.
+    Equal(usize),
+    /// The line at the given index of the old text is removed.
+    Delete(usize),
+    /// The line at the given index of the new text is inserted.
+    Insert(usize),
+}
+
+/// Prints a unified diff between the old and new contents of a file to stderr, in the style of
+/// `git diff`.
+pub fn print_unified_diff(path: &str, old: &str, new: &str) -> anyhow::Result<()> {
+    use std::io::Write as _;
+    use termcolor::WriteColor as _;
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let mut output = termcolor::StandardStream::stderr(output::colorize_stderr());
+    output.set_color(termcolor::ColorSpec::new().set_bold(true))?;
+    writeln!(output, "--- a/{path}")?;
+    writeln!(output, "+++ b/{path}")?;
+    output.reset()?;
+
+    for hunk in hunks(&ops) {
+        // Line numbers are one-based, except for empty ranges where they point at the line before
+        let (old_start, old_len, new_start, new_len) = hunk_range(&ops, hunk.clone());
+        output.set_color(termcolor::ColorSpec::new().set_fg(Some(termcolor::Color::Cyan)))?;
+        write!(
+            output,
+            "@@ -{},{old_len} +{},{new_len} @@",
+            old_start + usize::from(old_len > 0),
+            new_start + usize::from(new_len > 0)
+        )?;
+        output.reset()?;
+        writeln!(output)?;
+
+        for &op in &ops[hunk] {
+            let (sign, line, color) = match op {
+                Op::Equal(i) => (' ', old_lines[i], None),
+                Op::Delete(i) => ('-', old_lines[i], Some(termcolor::Color::Red)),
+                Op::Insert(j) => ('+', new_lines[j], Some(termcolor::Color::Green)),
+            };
+            if let Some(color) = color {
+                output.set_color(termcolor::ColorSpec::new().set_fg(Some(color)))?;
+            }
+            write!(output, "{sign}{}", line.trim_end_matches('\n'))?;
+            if color.is_some() {
+                output.reset()?;
+            }
+            writeln!(output)?;
+            if !line.ends_with('\n') {
+                writeln!(output, "\\ No newline at end of file")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Computes the edit script between two lists of lines using their longest common subsequence.
+///
+/// Manifests are small and edits are local, so the common prefix and suffix are skipped and the
+/// quadratic algorithm is only run on what is left in between.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // `lcs[i][j]` is the length of the longest common subsequence of `old_mid[i..]` and
+    // `new_mid[j..]`
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops: Vec<Op> = (0..prefix).map(Op::Equal).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            ops.push(Op::Equal(prefix + i));
+            i += 1;
+            j += 1;
+        } else if j == new_mid.len() || (i < old_mid.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete(prefix + i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(prefix + j));
+            j += 1;
+        }
+    }
+    ops.extend((old.len() - suffix..old.len()).map(Op::Equal));
+
+    ops
+}
+
+/// Groups the changes of an edit script into hunks with surrounding context, as ranges of `ops`.
+fn hunks(ops: &[Op]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    for (idx, op) in ops.iter().enumerate() {
+        if matches!(op, Op::Equal(_)) {
+            continue;
+        }
+        let start = idx.saturating_sub(CONTEXT_LINES);
+        let end = (idx + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            // Merge hunks whose context would overlap or touch
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// The zero-based start and the length of a hunk in the old and new text.
+fn hunk_range(ops: &[Op], hunk: std::ops::Range<usize>) -> (usize, usize, usize, usize) {
+    // Count the old and new lines before the hunk to find where it starts
+    let count = |ops: &[Op]| {
+        ops.iter().fold((0, 0), |(old, new), op| match op {
+            Op::Equal(_) => (old + 1, new + 1),
+            Op::Delete(_) => (old + 1, new),
+            Op::Insert(_) => (old, new + 1),
+        })
+    };
+    let (old_start, new_start) = count(&ops[..hunk.start]);
+    let (old_len, new_len) = count(&ops[hunk]);
+    (old_start, old_len, new_start, new_len)
+}
+
+#[cfg(test)]
+// Hunks are compared as lists of ranges, even if there is only one
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<&str> {
+        s.split_inclusive('\n').collect()
+    }
+
+    #[test]
+    fn change_at_start() {
+        let ops = diff_lines(&lines("a\nb\nc\nd\ne\n"), &lines("x\nb\nc\nd\ne\n"));
+        assert_eq!(
+            ops,
+            [
+                Op::Delete(0),
+                Op::Insert(0),
+                Op::Equal(1),
+                Op::Equal(2),
+                Op::Equal(3),
+                Op::Equal(4),
+            ]
+        );
+        assert_eq!(hunks(&ops), [0..5]);
+        assert_eq!(hunk_range(&ops, 0..5), (0, 4, 0, 4));
+    }
+
+    #[test]
+    fn change_at_end_without_trailing_newline() {
+        let (old, new) = (lines("a\nb\nc"), lines("a\nb\nc\nd"));
+        let ops = diff_lines(&old, &new);
+        // The last line changes, since it gains a newline
+        assert_eq!(
+            ops,
+            [
+                Op::Equal(0),
+                Op::Equal(1),
+                Op::Delete(2),
+                Op::Insert(2),
+                Op::Insert(3),
+            ]
+        );
+        assert_eq!((old[2], new[2]), ("c", "c\n"));
+        assert_eq!(hunks(&ops), [0..5]);
+        assert_eq!(hunk_range(&ops, 0..5), (0, 3, 0, 4));
+    }
+
+    #[test]
+    fn nearby_hunks_merge() {
+        let old = lines("a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n");
+        let new = lines("A\nb\nc\nd\ne\nf\nG\nh\ni\nj\n");
+        let ops = diff_lines(&old, &new);
+        // The context after the first change touches the context before the second one
+        assert_eq!(hunks(&ops), [0..12]);
+        assert_eq!(hunk_range(&ops, 0..12), (0, 10, 0, 10));
+    }
+
+    #[test]
+    fn distant_hunks_stay_apart() {
+        let old = lines("a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n");
+        let new = lines("A\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nL\n");
+        let ops = diff_lines(&old, &new);
+        assert_eq!(hunks(&ops), [0..5, 9..14]);
+        assert_eq!(hunk_range(&ops, 0..5), (0, 4, 0, 4));
+        assert_eq!(hunk_range(&ops, 9..14), (8, 4, 8, 4));
+    }
+
+    #[test]
+    fn insert_only() {
+        let ops = diff_lines(&lines("a\nb\nc\nd\ne\n"), &lines("a\nb\nc\nx\nd\ne\n"));
+        assert_eq!(
+            ops,
+            [
+                Op::Equal(0),
+                Op::Equal(1),
+                Op::Equal(2),
+                Op::Insert(3),
+                Op::Equal(3),
+                Op::Equal(4),
+            ]
+        );
+        assert_eq!(hunks(&ops), [0..6]);
+        assert_eq!(hunk_range(&ops, 0..6), (0, 5, 0, 6));
+    }
+
+    #[test]
+    fn insert_into_empty_file() {
+        let ops = diff_lines(&[], &lines("a\n"));
+        assert_eq!(ops, [Op::Insert(0)]);
+        assert_eq!(hunks(&ops), [0..1]);
+        // An empty old range is printed as `-0,0`
+        assert_eq!(hunk_range(&ops, 0..1), (0, 0, 0, 1));
+    }
+
+    #[test]
+    fn delete_only() {
+        let ops = diff_lines(
+            &lines("a\nb\nc\nd\ne\nf\ng\nh\n"),
+            &lines("a\nb\nc\nd\ne\nf\ng\n"),
+        );
+        assert_eq!(ops.last(), Some(&Op::Delete(7)));
+        assert_eq!(hunks(&ops), [4..8]);
+        assert_eq!(hunk_range(&ops, 4..8), (4, 4, 4, 3));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{cli, context, metadata};
+use std::collections;
+
+/// A package in the graph of a feature aspect.
+struct Node<'w> {
+    name: &'w str,
+    leaf_features: Vec<&'w str>,
+    is_external: bool,
+    is_changed: bool,
+}
+
+/// A dependency in the graph of a feature aspect, labelled with the params that forward the
+/// aspect to it.
+struct Edge {
+    from: usize,
+    to: usize,
+    params: Vec<String>,
+}
+
+/// Prints the in-scope dependency graph of a feature aspect to stdout, once the scope is computed.
+///
+/// `order` is the topological order of the workspace members, and `changed_packages` are the
+/// members that would be changed by the feature aspect.
+pub fn print_graph<'w>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context<'w>,
+    order: &[usize],
+    changed_packages: &collections::HashSet<&cargo_metadata::PackageId>,
+    format: cli::GraphFormat,
+) {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut node_indices: collections::HashMap<&cargo_metadata::PackageId, usize> =
+        collections::HashMap::new();
+    let mut edges = Vec::new();
+
+    let mut add_node = |nodes: &mut Vec<Node<'w>>, id: &'w cargo_metadata::PackageId| {
+        *node_indices.entry(id).or_insert_with(|| {
+            let (name, leaf_features) = crate::why::describe_node(ws, ctx, id);
+            nodes.push(Node {
+                name,
+                leaf_features,
+                is_external: ws.member(id).is_none(),
+                is_changed: changed_packages.contains(id),
+            });
+            nodes.len() - 1
+        })
+    };
+
+    // Members are added first, so that they are listed in topological order
+    let packages: Vec<&cargo_metadata::Package> = order
+        .iter()
+        .map(|&idx| &ws.members[idx])
+        .filter(|p| ctx.in_scope_packages.contains(&p.id))
+        .collect();
+    for package in &packages {
+        add_node(&mut nodes, &package.id);
+    }
+    for package in &packages {
+        let from = add_node(&mut nodes, &package.id);
+        for (_, id, params) in crate::dependency_params(ctx, ws, package) {
+            let to = add_node(&mut nodes, id);
+            edges.push(Edge { from, to, params });
+        }
+    }
+
+    let feature = ctx.feature_name.as_ref();
+    match format {
+        cli::GraphFormat::Dot => print_dot(feature, &nodes, &edges),
+        cli::GraphFormat::Mermaid => print_mermaid(&nodes, &edges),
+    }
+}
+
+fn print_dot(feature: &str, nodes: &[Node], edges: &[Edge]) {
+    println!("digraph {} {{", dot_string(feature));
+    println!("    rankdir=LR;");
+    println!("    node [shape=box];");
+    for (idx, node) in nodes.iter().enumerate() {
+        let mut attrs = vec![format!("label={}", dot_string(&label(node)))];
+        let mut styles = Vec::new();
+        if !node.leaf_features.is_empty() {
+            styles.push("filled");
+            attrs.push("fillcolor=\"palegreen\"".to_owned());
+        }
+        if node.is_external {
+            styles.push("dashed");
+        }
+        if node.is_changed {
+            styles.push("bold");
+            attrs.push("color=\"darkorange\"".to_owned());
+        }
+        if !styles.is_empty() {
+            attrs.push(format!("style={}", dot_string(&styles.join(","))));
+        }
+        println!("    n{idx} [{}];", attrs.join(", "));
+    }
+    for edge in edges {
+        println!(
+            "    n{} -> n{} [label={}];",
+            edge.from,
+            edge.to,
+            dot_string(&edge.params.join(", "))
+        );
+    }
+    println!("}}");
+}
+
+fn print_mermaid(nodes: &[Node], edges: &[Edge]) {
+    println!("graph LR");
+    for (idx, node) in nodes.iter().enumerate() {
+        println!("    n{idx}[{}]", mermaid_string(&label(node)));
+    }
+    for edge in edges {
+        if edge.params.is_empty() {
+            println!("    n{} --> n{}", edge.from, edge.to);
+        } else {
+            println!(
+                "    n{} -->|{}| n{}",
+                edge.from,
+                mermaid_string(&edge.params.join(", ")),
+                edge.to
+            );
+        }
+    }
+
+    println!("    classDef leaf fill:#98fb98;");
+    println!("    classDef changed stroke:#ff8c00,stroke-width:3px;");
+    println!("    classDef external stroke-dasharray:5 5;");
+    print_mermaid_class("leaf", nodes, |n| !n.leaf_features.is_empty());
+    print_mermaid_class("changed", nodes, |n| n.is_changed);
+    print_mermaid_class("external", nodes, |n| n.is_external);
+}
+
+fn print_mermaid_class(class: &str, nodes: &[Node], has_class: impl Fn(&Node) -> bool) {
+    let ids: Vec<String> = (0..nodes.len())
+        .filter(|&idx| has_class(&nodes[idx]))
+        .map(|idx| format!("n{idx}"))
+        .collect();
+    if !ids.is_empty() {
+        println!("    class {} {class};", ids.join(","));
+    }
+}
+
+/// The label of a node, which includes its leaf features if it has any.
+fn label(node: &Node) -> String {
+    if node.leaf_features.is_empty() {
+        node.name.to_owned()
+    } else {
+        format!("{}[{}]", node.name, node.leaf_features.join(","))
+    }
+}
+
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn mermaid_string(s: &str) -> String {
+    // Mermaid doesn't support escaping quotes, only HTML entities
+    format!("\"{}\"", s.replace('"', "#quot;"))
+}
@@ -1,21 +1,40 @@
 #![warn(clippy::all, clippy::cargo)]
 #![doc = include_str!("../README.md")]
 
-use std::{borrow, cmp, fs, process};
+use std::{collections, process};
 
 mod cli;
+mod config;
 mod context;
+mod diff;
+mod graph;
+mod manifest;
 mod metadata;
 mod output;
+mod param;
+mod pattern;
+mod suggest;
 mod topo;
+mod why;
 
 fn main() {
-    tracing_subscriber::fmt::init();
+    // Logs go to stderr like all other messages, since stdout is reserved for machine-readable
+    // output like `--message-format json`
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
     let result = {
         let command: cli::Command = clap::Parser::parse();
         match command {
-            cli::Command::FeatureAspect(args) => run_feature_aspect(&args),
+            cli::Command::FeatureAspect(args) => match &args.action {
+                Some(cli::Action::Remove(remove_args)) => run_remove(remove_args),
+                Some(cli::Action::Rename(rename_args)) => run_rename(rename_args),
+                Some(cli::Action::Suggest(suggest_args)) => run_suggest(suggest_args),
+                Some(cli::Action::Why(why_args)) => run_why(why_args),
+                None => run_feature_aspect(&args),
+            },
         }
     };
 
@@ -29,66 +48,426 @@ fn main() {
 }
 
 fn run_feature_aspect(args: &cli::FeatureAspectArgs) -> anyhow::Result<()> {
-    let mut ctx = context::Context::new(args)?;
-    let feature = ctx.feature_name.as_ref();
+    let metadata = resolve_metadata(&args.common.metadata)?;
+    let aspect_configs = config::workspace_aspects(&metadata)?;
+
+    tracing::debug!("enumerating workspace members");
+    let ws = metadata::Workspace::new(metadata)?;
+
+    let mut contexts = if args.name.is_some() || !args.leaf_features.is_empty() {
+        vec![context::Context::new(args, &ws)?]
+    } else {
+        select_configured_aspects(args, &aspect_configs)?
+    };
+
+    // Collect the changes of all aspects first, so that each manifest only needs to be edited once
+    let mut package_changes: Vec<Vec<manifest::FeatureEdit>> =
+        ws.members.iter().map(|_| Vec::new()).collect();
+
+    for ctx in &mut contexts {
+        let feature = ctx.feature_name.as_ref();
+        output::shell_status(
+            "Processing",
+            &format!("feature aspect for feature {feature:?} in the workspace"),
+        )?;
+
+        let order = compute_scope(&ws, ctx)?;
+
+        for &idx in &order {
+            let package = &ws.members[idx];
+            if ctx.in_scope_packages.contains(&package.id) {
+                // Unfortunately at this point we cannot trust the `package.features` for diffing,
+                // because some of the metadata features might be implicitly generated.  We will
+                // instead need to check against the actual manifest file no matter what.
+                package_changes[idx].push(manifest::FeatureEdit::Update(describe_changes(
+                    ctx, &ws, package,
+                )));
+                if let Some(changes) = describe_default_feature(ctx, &ws, package) {
+                    package_changes[idx].push(manifest::FeatureEdit::Update(changes));
+                }
+            } else if ctx.prune {
+                if let Some(edit) = describe_pruning(ctx, &ws, package)? {
+                    package_changes[idx].push(edit);
+                }
+            }
+        }
+
+        if let Some(format) = args.graph {
+            // Find out which packages would change for this aspect only, instead of applying the
+            // changes of all aspects at the end
+            let mut changed_packages = collections::HashSet::new();
+            for (package, edits) in ws.members.iter().zip(&mut package_changes) {
+                let edits = std::mem::take(edits);
+                if !edits.is_empty() && manifest::would_change(package, edits)? {
+                    changed_packages.insert(&package.id);
+                }
+            }
+            graph::print_graph(&ws, ctx, &order, &changed_packages, format);
+        }
+    }
+
+    if args.graph.is_some() {
+        return Ok(());
+    }
+
+    apply_edits(&ws, package_changes, Vec::new(), &args.common)
+}
+
+fn run_remove(args: &cli::RemoveArgs) -> anyhow::Result<()> {
+    let feature = args.name.as_str();
+    output::shell_status(
+        "Processing",
+        &format!("removal of feature aspect for feature {feature:?} from the workspace"),
+    )?;
+
+    let metadata = resolve_metadata(&args.common.metadata)?;
+    let aspect_configs = config::workspace_aspects(&metadata)?;
+    let ws = metadata::Workspace::new(metadata)?;
+
+    // The leaf features are only needed to keep them, so they don't have to be known exactly.  The
+    // context is needed either way for the local names of the aspect feature.
+    let ctx = if !args.leaf_features.is_empty() {
+        context::Context::from_leaf_features(
+            feature,
+            &args.leaf_features,
+            cli::Direction::default(),
+        )?
+    } else if let Some((key, aspect)) = find_configured_aspect(&aspect_configs, feature) {
+        context::Context::from_config(key, aspect, None)?
+    } else if args.remove_leaf_features {
+        context::Context::from_leaf_features(feature, &[], cli::Direction::default())?
+    } else {
+        // Guessing is not good enough, since removing a leaf feature breaks the code gated on it
+        anyhow::bail!(
+            "no feature aspect named {feature:?} in `[workspace.metadata.feature-aspect]`, specify its --leaf-feature so that leaf features are kept, or pass --remove-leaf-features"
+        );
+    };
+
+    let package_edits = ws
+        .members
+        .iter()
+        .map(|package| {
+            let local_feature = ctx.local_feature_name(&ws, &package.id);
+            let keep_feature =
+                !args.remove_leaf_features && ctx.leaf_features(package).contains(&local_feature);
+            if keep_feature {
+                tracing::debug!(package = package.name, "keeping leaf feature");
+            }
+            describe_removal(&ctx, &ws, package, keep_feature)
+        })
+        .collect();
+
+    apply_edits(&ws, package_edits, Vec::new(), &args.common)
+}
+
+fn run_rename(args: &cli::RenameArgs) -> anyhow::Result<()> {
+    let (from, to) = (args.from.as_str(), args.to.as_str());
     output::shell_status(
         "Processing",
-        &format!("feature aspect for feature {feature:?} in the workspace"),
+        &format!("renaming of feature aspect {from:?} to {to:?} in the workspace"),
     )?;
 
+    let metadata = resolve_metadata(&args.common.metadata)?;
+    let aspect_configs = config::workspace_aspects(&metadata)?;
+    let ws = metadata::Workspace::new(metadata)?;
+
+    // The feature aspect would be recreated under its old name if its settings weren't renamed too
+    if let Some((_, aspect)) = find_configured_aspect(&aspect_configs, from) {
+        if aspect_configs.contains_key(to) || find_configured_aspect(&aspect_configs, to).is_some()
+        {
+            anyhow::bail!(
+                "cannot rename feature aspect {from:?} to {to:?} since a feature aspect named {to:?} is already declared in `[workspace.metadata.feature-aspect]`"
+            );
+        }
+
+        for leaf_feature in &aspect.leaf_features {
+            let feature = leaf_feature
+                .split_once('/')
+                .map_or(leaf_feature.as_str(), |(_, f)| f);
+            let pattern = pattern::Pattern::parse(feature)?;
+            if pattern.as_exact().is_none() && pattern.matches(from) && !pattern.matches(to) {
+                output::shell_warn(&format!(
+                    "leaf feature {leaf_feature:?} of feature aspect {from:?} does not match the new name {to:?}, it must be updated by hand"
+                ))?;
+            }
+        }
+    }
+
+    let ctx = context::Context::from_leaf_features(from, &[], cli::Direction::default())?;
+    let package_edits = ws
+        .members
+        .iter()
+        .map(|package| describe_rename(&ctx, &ws, package, to, args.alias))
+        .collect::<anyhow::Result<_>>()?;
+    let workspace_edits = vec![manifest::FeatureEdit::RenameAspectSettings {
+        from: from.to_owned(),
+        to: to.to_owned(),
+    }];
+
+    apply_edits(&ws, package_edits, workspace_edits, &args.common)
+}
+
+fn run_suggest(args: &cli::SuggestArgs) -> anyhow::Result<()> {
+    output::shell_status("Processing", "feature propagation chains in the workspace")?;
+
+    let metadata = resolve_metadata(&args.metadata)?;
+    let ws = metadata::Workspace::new(metadata)?;
+    suggest::suggest_aspects(&ws)
+}
+
+fn run_why(args: &cli::WhyArgs) -> anyhow::Result<()> {
+    let metadata = resolve_metadata(&args.metadata)?;
+    let aspect_configs = config::workspace_aspects(&metadata)?;
+    let ws = metadata::Workspace::new(metadata)?;
+
+    let mut ctx = if args.leaf_features.is_empty() {
+        let Some((key, aspect)) = find_configured_aspect(&aspect_configs, &args.name) else {
+            anyhow::bail!(
+                "no feature aspect named {:?} in `[workspace.metadata.feature-aspect]`, specify its --leaf-feature instead",
+                args.name
+            );
+        };
+        context::Context::from_config(key, aspect, None)?
+    } else {
+        context::Context::from_leaf_features(
+            &args.name,
+            &args.leaf_features,
+            args.direction.unwrap_or_default(),
+        )?
+    };
+
+    let Some(package) = ws.members.iter().find(|p| p.name == args.package) else {
+        anyhow::bail!(
+            "package {:?} is not a member of the workspace",
+            args.package
+        );
+    };
+
+    compute_scope(&ws, &mut ctx)?;
+    why::explain(&ws, &ctx, package)
+}
+
+fn resolve_metadata(args: &cli::MetadataArgs) -> anyhow::Result<cargo_metadata::Metadata> {
     tracing::debug!("resolving workspace metadata");
-    let metadata = metadata::resolve_ws(
+    metadata::resolve_ws(
         args.manifest.manifest_path.as_deref(),
         args.locked,
         args.offline,
-    )?;
-    tracing::debug!("enumerating workspace members");
-    let mut packages = metadata::find_ws_members(metadata);
-    tracing::debug!("doing topological sort of workspace members");
-    topo::sort_packages(&mut packages)?;
+    )
+}
 
-    for package in &packages {
-        visit_package(package, &mut ctx)?;
+/// Applies the edits for each workspace member (in the same order as `ws.members`), as well as the
+/// edits of the workspace settings in the root manifest, to the manifest files, or only reports
+/// them for `--dry-run` and `--verify`.
+fn apply_edits(
+    ws: &metadata::Workspace,
+    mut package_edits: Vec<Vec<manifest::FeatureEdit>>,
+    workspace_edits: Vec<manifest::FeatureEdit>,
+    args: &cli::CommonArgs,
+) -> anyhow::Result<()> {
+    let mut reporter = output::Reporter::new(args.message_format, args.verify);
+    let write = !(args.dry_run || args.verify);
+    let diff_root = args.diff.then_some(ws.root.as_path());
+    let selected = select_members(ws, &args.workspace)?;
+    let mut changed_packages = Vec::new();
+    let mut has_changes = false;
+
+    // The root manifest might belong to a package, whose manifest is only edited once
+    let root_manifest = ws.root.join("Cargo.toml");
+    match ws
+        .members
+        .iter()
+        .position(|p| p.manifest_path == root_manifest)
+    {
+        Some(idx) => package_edits[idx].extend(workspace_edits),
+        None if !workspace_edits.is_empty() => {
+            has_changes |= manifest::apply_workspace_changes(
+                &root_manifest,
+                workspace_edits,
+                write,
+                diff_root,
+                &mut reporter,
+            )?;
+        }
+        None => {}
     }
 
-    if ctx.verify && ctx.has_changes {
+    for ((package, edits), is_selected) in ws.members.iter().zip(package_edits).zip(selected) {
+        if !is_selected {
+            tracing::debug!(
+                package = package.name,
+                "skipping package that is not selected"
+            );
+        } else if !edits.is_empty()
+            && manifest::apply_changes(package, edits, write, diff_root, &mut reporter)?
+        {
+            changed_packages.push(package.name.as_str());
+        }
+    }
+
+    has_changes |= !changed_packages.is_empty();
+    reporter.report_summary(&output::Summary {
+        changed_packages,
+        dry_run: args.dry_run,
+        verify: args.verify,
+        success: !(args.verify && has_changes),
+    })?;
+
+    if args.verify && has_changes {
         anyhow::bail!("failing because --verify was passed and changes were detected");
     }
 
     Ok(())
 }
 
+/// Determines which workspace members (in the same order as `ws.members`) are selected with
+/// `--package`, `--workspace` and `--exclude`.
+///
+/// Unlike cargo, all members are selected by default, not only the `default-members`, and packages
+/// are only matched by name.
+fn select_members(
+    ws: &metadata::Workspace,
+    args: &clap_cargo::Workspace,
+) -> anyhow::Result<Vec<bool>> {
+    for name in args.package.iter().chain(&args.exclude) {
+        if !ws.members.iter().any(|p| &p.name == name) {
+            anyhow::bail!("package {name:?} is not a member of the workspace");
+        }
+    }
+
+    let all = args.workspace || args.all || args.package.is_empty();
+    Ok(ws
+        .members
+        .iter()
+        .map(|p| (all || args.package.contains(&p.name)) && !args.exclude.contains(&p.name))
+        .collect())
+}
+
+/// Finds the feature aspect with the given name in `[workspace.metadata.feature-aspect]`.
+fn find_configured_aspect<'a>(
+    aspect_configs: &'a collections::BTreeMap<String, config::AspectConfig>,
+    name: &str,
+) -> Option<(&'a String, &'a config::AspectConfig)> {
+    aspect_configs
+        .iter()
+        .find(|(key, aspect)| aspect.name.as_deref().unwrap_or(key) == name)
+}
+
+/// Creates contexts for the feature aspects declared in the workspace metadata, limited to the
+/// ones selected with `--aspect` if any.
+fn select_configured_aspects<'a>(
+    args: &'a cli::FeatureAspectArgs,
+    aspect_configs: &'a collections::BTreeMap<String, config::AspectConfig>,
+) -> anyhow::Result<Vec<context::Context<'a>>> {
+    if aspect_configs.is_empty() {
+        anyhow::bail!(
+            "Must specify --name or --leaf-feature, or else declare feature aspects in `[workspace.metadata.feature-aspect]`"
+        );
+    }
+
+    for name in &args.aspects {
+        if !aspect_configs.contains_key(name) {
+            anyhow::bail!(
+                "no feature aspect named {name:?} in `[workspace.metadata.feature-aspect]`"
+            );
+        }
+    }
+
+    aspect_configs
+        .iter()
+        .filter(|(key, _)| args.aspects.is_empty() || args.aspects.contains(key))
+        .map(|(key, aspect)| context::Context::from_config(key, aspect, Some(args)))
+        .collect()
+}
+
+/// Computes the scope of a feature aspect, recording the in-scope packages in the context.
+///
+/// Returns the workspace members in topological order, as indices into `ws.members`.
+fn compute_scope<'a>(
+    ws: &'a metadata::Workspace,
+    ctx: &mut context::Context<'a>,
+) -> anyhow::Result<Vec<usize>> {
+    tracing::debug!("doing topological sort of workspace members");
+    let order = topo::sort_packages(ws, ctx)?;
+
+    match ctx.direction {
+        cli::Direction::Upward => {
+            for package in &ws.externals {
+                visit_external_package(package, ctx);
+            }
+
+            // In topological order, a single pass finds the full scope of the feature aspect,
+            // but if there are dependency cycles we need to keep going until the scope stops
+            // changing.
+            loop {
+                let mut scope_changed = false;
+                for &idx in &order {
+                    scope_changed |= visit_package(&ws.members[idx], ws, ctx);
+                }
+                if !scope_changed {
+                    break;
+                }
+            }
+        }
+        cli::Direction::Downward => {
+            let mut queue: Vec<&cargo_metadata::Package> = ws
+                .members
+                .iter()
+                .filter(|p| !ctx.leaf_features(p).is_empty())
+                .collect();
+            while let Some(package) = queue.pop() {
+                queue.extend(visit_package_downward(package, ws, ctx));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// Checks whether a package outside of the workspace is a leaf of the feature aspect.
+///
+/// We can't edit such packages, so they never propagate the aspect further on their own; only the
+/// workspace members that directly depend on them do.
 #[tracing::instrument(skip_all, fields(package = package.name))]
-fn visit_package<'a>(
+fn visit_external_package<'a>(
     package: &'a cargo_metadata::Package,
     ctx: &mut context::Context<'a>,
-) -> anyhow::Result<()> {
-    let pkg_name = &package.name;
-    let mut is_in_scope = false;
-    let mut referenced_leaf_features = Vec::new();
+) {
+    let leaf_features = ctx.leaf_features(package);
+    if !leaf_features.is_empty() {
+        tracing::debug!(?leaf_features, "external package has leaf features");
+        ctx.in_scope_packages.insert(&package.id);
+        ctx.external_leaf_features
+            .insert(&package.id, leaf_features);
+    }
+}
 
-    // Here we do lots of `Vec::contains` but since these are small vecs, it is not worth it
-    // to do some fancy hash set stuff, since hashing all the strings will probably take more
-    // time than just traversing the vec.
+/// Checks whether a workspace member is in scope for the feature aspect, and records it if so.
+///
+/// Returns true if the package was not known to be in scope before.
+#[tracing::instrument(skip_all, fields(package = package.name))]
+fn visit_package<'a>(
+    package: &'a cargo_metadata::Package,
+    ws: &metadata::Workspace,
+    ctx: &mut context::Context<'a>,
+) -> bool {
+    if ctx.in_scope_packages.contains(&package.id) {
+        return false;
+    }
+    if ctx.is_stopped(ws, package) {
+        tracing::debug!("package is a stop point for the feature aspect");
+        return false;
+    }
 
-    for feature in package.features.keys() {
-        if ctx.unqualified_leaf_features.contains(&feature.as_str())
-            || ctx.qualified_leaf_features.contains(&(pkg_name, feature))
-        {
-            tracing::debug!(feature, "package has leaf feature");
-            is_in_scope = true;
+    let mut is_in_scope = false;
 
-            if ctx.feature_name.as_ref() != feature.as_str() {
-                // It might be the case that our main feature is named something totally different
-                // from the leaf feature, which means that we should add the leaf feature as a
-                // dependency for our main feature.
-                referenced_leaf_features.push(feature.as_str());
-            }
-        }
+    for feature in ctx.leaf_features(package) {
+        tracing::debug!(feature, "package has leaf feature");
+        is_in_scope = true;
     }
 
-    for dependency in &package.dependencies {
-        if ctx.in_scope_packages.contains(dependency.name.as_str()) {
+    for dependency in ctx.dependencies(ws, package) {
+        if ctx.in_scope_dependency(ws, package, dependency).is_some() {
             tracing::debug!(
                 dependency = dependency.name,
                 "package depends on in-scope dependency"
@@ -98,270 +477,433 @@ fn visit_package<'a>(
     }
 
     if is_in_scope {
-        tracing::debug!("package considered in scope for feature aspect; ensuring feature exists");
-        ctx.in_scope_packages.insert(pkg_name);
+        tracing::debug!("package considered in scope for feature aspect");
+        ctx.in_scope_packages.insert(&package.id);
+    }
+
+    is_in_scope
+}
 
-        // Unfortunately at this point we cannot trust the `package.features` for diffing, because
-        // some of the metadata features might be implicitly generated.  We will instead need to
-        // check against the actual manifest file no matter what.
-        visit_aspect_feature(package, ctx, &referenced_leaf_features)?;
+/// Records a workspace member as in scope for a downward feature aspect, since it is a root or a
+/// dependency of an in-scope package.
+///
+/// Returns the workspace members that the package depends on, which are then in scope too.  We
+/// can't edit packages outside of the workspace, so the aspect is never propagated to those.
+#[tracing::instrument(skip_all, fields(package = package.name))]
+fn visit_package_downward<'a>(
+    package: &'a cargo_metadata::Package,
+    ws: &'a metadata::Workspace,
+    ctx: &mut context::Context<'a>,
+) -> Vec<&'a cargo_metadata::Package> {
+    if ctx.is_stopped(ws, package) {
+        tracing::debug!("package is a stop point for the feature aspect");
+        return Vec::new();
+    }
+    if !ctx.in_scope_packages.insert(&package.id) {
+        return Vec::new();
     }
 
-    Ok(())
+    tracing::debug!("package considered in scope for feature aspect");
+    ctx.dependencies(ws, package)
+        .filter_map(|dep| ws.resolve_member(dep))
+        .filter_map(|id| ws.member(id))
+        .collect()
 }
 
-fn visit_aspect_feature(
+/// The in-scope dependencies of a package by local name, along with the params of the aspect
+/// feature that forward the aspect to each of them, as generated by [`describe_changes`].
+fn dependency_params<'w>(
+    ctx: &context::Context,
+    ws: &'w metadata::Workspace,
     package: &cargo_metadata::Package,
-    ctx: &mut context::Context,
-    referenced_leaf_features: &[&str],
-) -> anyhow::Result<()> {
-    let feature = ctx.feature_name.as_ref();
-    let changes = describe_changes(ctx, package, referenced_leaf_features, feature);
-    let has_changes = handle_feature_changes(ctx, package, feature, changes)?;
-    ctx.has_changes |= has_changes;
-    Ok(())
-}
+) -> Vec<(String, &'w cargo_metadata::PackageId, Vec<String>)> {
+    let changes = describe_changes(ctx, ws, package);
+    let mut deps: Vec<(String, &cargo_metadata::PackageId, Vec<String>)> = Vec::new();
+
+    for dep in ctx.dependencies(ws, package) {
+        let dep_name = metadata::local_name(dep);
+        let Some(id) = ctx.in_scope_dependency(ws, package, dep) else {
+            continue;
+        };
+        if deps.iter().any(|(n, _, _)| n == dep_name) {
+            continue;
+        }
+
+        let params = changes
+            .params_to_add
+            .iter()
+            .filter(|param| {
+                param
+                    .split_once('/')
+                    .is_some_and(|(d, _)| d.strip_suffix('?').unwrap_or(d) == dep_name)
+            })
+            .cloned()
+            .collect();
+        deps.push((dep_name.to_owned(), id, params));
+    }
 
-struct Changes<'a> {
-    params_to_add: Vec<borrow::Cow<'a, str>>,
-    params_to_remove: Vec<borrow::Cow<'a, str>>,
+    deps
 }
 
 /// Generates the changes we would like to make to the feature aspect for a specific package.
-fn describe_changes<'a>(
-    ctx: &'a context::Context,
-    package: &'a cargo_metadata::Package,
-    referenced_leaf_features: &[&'a str],
-    feature: &str,
-) -> Changes<'a> {
+fn describe_changes(
+    ctx: &context::Context,
+    ws: &metadata::Workspace,
+    package: &cargo_metadata::Package,
+) -> manifest::FeatureChanges {
+    let feature = ctx.local_feature_name(ws, &package.id);
+
+    // It might be the case that our main feature is named something totally different from the
+    // leaf feature, which means that we should add the leaf feature as a dependency for our main
+    // feature.
+    let referenced_leaf_features: Vec<&str> = ctx
+        .leaf_features(package)
+        .into_iter()
+        .filter(|&leaf_feature| leaf_feature != feature)
+        .collect();
+
     // Params to possibly add, however a check will be made later to remove duplicates
-    let mut params_to_add: Vec<borrow::Cow<str>> = Vec::new();
+    let mut params_to_add = Vec::new();
     // Params to remove, however a check will be made later to see if they actually exist
-    let mut params_to_remove: Vec<borrow::Cow<str>> = Vec::new();
+    let mut params_to_remove = Vec::new();
+    // The first reason for each param wins, in case it is generated for several reasons
+    let mut reasons = collections::BTreeMap::new();
+
+    // The same dependency might be listed in several dependency sections, e.g. both in
+    // `[dependencies]` and `[build-dependencies]`, so merge those entries first.  The dependency
+    // is only considered optional if it is optional in all of the sections.  We match the scope
+    // on the actual package, but need to use the local name of renamed dependencies in the
+    // feature params.
+    let mut in_scope_deps: Vec<(&str, bool, &cargo_metadata::PackageId)> = Vec::new();
+    for dep in ctx.dependencies(ws, package) {
+        if let Some(id) = ctx.in_scope_dependency(ws, package, dep) {
+            let dep_name = metadata::local_name(dep);
+            if let Some((_, optional, _)) =
+                in_scope_deps.iter_mut().find(|(n, _, _)| *n == dep_name)
+            {
+                *optional &= dep.optional;
+            } else {
+                in_scope_deps.push((dep_name, dep.optional, id));
+            }
+        }
+    }
 
     // Ensure that we propagate the feature to our dependencies.
-    for dep in &package.dependencies {
-        if ctx.in_scope_packages.contains(dep.name.as_str()) {
-            let non_optional_dep_spec = format!("{}/{}", dep.name, feature);
-            let optional_dep_spec = format!("{}?/{}", dep.name, feature);
+    for &(dep_name, optional, id) in &in_scope_deps {
+        // External dependencies don't get the aspect feature, so we forward their leaf features
+        // directly instead.
+        let dep_features = match ctx.external_leaf_features.get(id) {
+            Some(leaf_features) => leaf_features.as_slice(),
+            None => &[ctx.local_feature_name(ws, id)],
+        };
+
+        for dep_feature in dep_features {
+            let non_optional_dep_spec = format!("{dep_name}/{dep_feature}");
+            let optional_dep_spec = format!("{dep_name}?/{dep_feature}");
 
             // Gracefully handle when a dependency might have changed its "optional status" from
             // previous runs.
-            let (dep_spec_to_add, dep_spec_to_remove) = if dep.optional {
+            let (dep_spec_to_add, dep_spec_to_remove) = if optional {
                 (optional_dep_spec, non_optional_dep_spec)
             } else {
                 (non_optional_dep_spec, optional_dep_spec)
             };
 
-            params_to_add.push(dep_spec_to_add.into());
-            params_to_remove.push(dep_spec_to_remove.into());
+            reasons
+                .entry(dep_spec_to_add.clone())
+                .or_insert(output::ChangeReason::Dependency);
+            reasons
+                .entry(dep_spec_to_remove.clone())
+                .or_insert(output::ChangeReason::OptionalDependency);
+            params_to_add.push(dep_spec_to_add);
+            params_to_remove.push(dep_spec_to_remove);
         }
     }
 
-    // Ensure extra params are present
-    for &param in ctx
-        .extra_feature_params
-        .as_slice()
-        .iter()
-        .chain(referenced_leaf_features.iter())
-    {
-        let should_include = if let Some((prefix, suffix)) = param.split_once(':') {
-            if prefix == "dep" {
-                // Special-case: we only include dep references if the dep actually exists.
-                // This would otherwise be very annoying to express with some sort of CLI flags,
-                // so we just handle it by default.
-                package.dependencies.iter().any(|d| d.name == suffix)
-            } else {
-                true
-            }
-        } else {
-            true
-        };
+    // Ensure extra params are present, expanding `{dep}` once per in-scope dependency and skipping
+    // params whose conditions don't hold
+    let in_scope_dep_names: Vec<&str> = in_scope_deps.iter().map(|&(n, _, _)| n).collect();
+    for (param, holds) in ctx.expand_extra_feature_params(ws, package, &in_scope_dep_names) {
+        if holds {
+            reasons
+                .entry(param.clone())
+                .or_insert(output::ChangeReason::ExtraParam);
+            params_to_add.push(param);
+        }
+    }
+    for param in referenced_leaf_features {
+        reasons
+            .entry(param.to_owned())
+            .or_insert(output::ChangeReason::LeafFeature);
+        params_to_add.push(param.to_owned());
+    }
 
-        if should_include {
-            params_to_add.push(param.into());
+    // Any other param forwarding the feature aspect to a dependency is stale, e.g. because the
+    // dependency is no longer in scope, and must be removed since cargo would reject it.
+    if let Some(current_params) = package.features.get(feature) {
+        for param in current_params {
+            if ctx.is_dependency_param(ws, package, param) && !params_to_add.contains(param) {
+                reasons
+                    .entry(param.clone())
+                    .or_insert(output::ChangeReason::Stale);
+                params_to_remove.push(param.clone());
+            }
         }
     }
 
-    Changes {
+    manifest::FeatureChanges {
+        feature: feature.to_owned(),
         params_to_add,
         params_to_remove,
+        reasons,
+        sort: ctx.sort,
     }
 }
 
-/// Somewhat awkward function broken out from `visit_aspect_feature`
-///
-/// Returns true if actual changes compared to the physical manifest file were detected. We can't
-/// borrow `ctx` by mutable reference here since it has multiple per-field borrows in
-/// `visit_aspect_feature`, so we do the update to `has_changes` there instead.
-fn handle_feature_changes(
+/// Generates the changes to the `default` feature of an in-scope package, if the feature aspect
+/// should be included in or excluded from it.
+fn describe_default_feature(
     ctx: &context::Context,
+    ws: &metadata::Workspace,
     package: &cargo_metadata::Package,
-    feature: &str,
-    changes: Changes,
-) -> anyhow::Result<bool> {
-    // Awkward sorting functions because `.sort_by_key()` doesn't handle sort keys with
-    // lifetimes nicely
-    fn feature_param_sort_key(param: &str) -> (bool, &str) {
-        if param.starts_with("dep:") {
-            (false, param)
-        } else {
-            (true, param)
-        }
-    }
+) -> Option<manifest::FeatureChanges> {
+    let feature = ctx.local_feature_name(ws, &package.id);
+    let is_default = package
+        .features
+        .get("default")
+        .is_some_and(|params| params.iter().any(|p| p == feature));
+
+    let (params_to_add, params_to_remove) = match ctx.default_feature? {
+        cli::DefaultFeature::Include if !is_default => (vec![feature.to_owned()], Vec::new()),
+        cli::DefaultFeature::Exclude if is_default => (Vec::new(), vec![feature.to_owned()]),
+        _ => return None,
+    };
+
+    Some(manifest::FeatureChanges {
+        feature: "default".to_owned(),
+        params_to_add,
+        params_to_remove,
+        reasons: [(feature.to_owned(), output::ChangeReason::DefaultFeature)].into(),
+        // Leave the order of the default features alone, since it is not ours
+        sort: false,
+    })
+}
+
+/// Determines whether the feature aspect should be removed from a package that is not in scope.
+fn describe_pruning(
+    ctx: &context::Context,
+    ws: &metadata::Workspace,
+    package: &cargo_metadata::Package,
+) -> anyhow::Result<Option<manifest::FeatureEdit>> {
+    let feature = ctx.local_feature_name(ws, &package.id);
+    let pkg_name = &package.name;
+    let Some(params) = package.features.get(feature) else {
+        return Ok(None);
+    };
 
-    fn feature_param_ordering(a: &str, b: &str) -> cmp::Ordering {
-        feature_param_sort_key(a).cmp(&feature_param_sort_key(b))
+    // Only remove features that look like we created them, so that we don't destroy anything
+    // that was written by hand.  Empty features are only generated at the bottom of downward
+    // feature aspects, so leave them alone otherwise.
+    if params.is_empty() && ctx.direction == cli::Direction::Upward {
+        tracing::debug!("not pruning empty feature");
+        return Ok(None);
     }
 
-    // Here we do lots of `Vec::contains` but since these are small vecs, it is not worth it
-    // to do some fancy hash set stuff, since hashing all the strings will probably take more
-    // time than just traversing the vec.
+    let foreign_params: Vec<&str> = params
+        .iter()
+        .map(String::as_str)
+        .filter(|param| !ctx.is_generated_param(ws, package, param))
+        .collect();
+    if !foreign_params.is_empty() {
+        output::shell_warn(&format!(
+            "not pruning feature {feature:?} from package {pkg_name} since it has params not generated by this tool: {foreign_params:?}"
+        ))?;
+        return Ok(None);
+    }
 
-    let pkg_name = &package.name;
-    // TODO: is it clean to just make the file change here? Feels a bit dirty/hidden away...
-    let contents = fs::read_to_string(&package.manifest_path)?;
-    let mut has_changes = false;
+    // Removing the feature would break any other feature that enables it.
+    let referencing_features: Vec<&str> = package
+        .features
+        .iter()
+        .filter(|(_, params)| params.iter().any(|p| p == feature))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !referencing_features.is_empty() {
+        output::shell_warn(&format!(
+            "not pruning feature {feature:?} from package {pkg_name} since it is enabled by features {referencing_features:?}"
+        ))?;
+        return Ok(None);
+    }
 
-    let Changes {
-        mut params_to_add,
-        mut params_to_remove,
-    } = changes;
-
-    if ctx.dry_run || ctx.verify {
-        // We need to parse the actual manifest file to remove implicit features that get
-        // auto-generated by cargo at runtime
-        let doc: toml::Table = toml::from_str(&contents)?;
-
-        let current_params = doc
-            // [features]
-            // ...
-            .get("features")
-            // [features]
-            // my-feature = ...
-            .and_then(|f| f.get(feature))
-            // [features]
-            // my-feature = [...]
-            .and_then(|f| f.as_array())
-            // [features]
-            // my-feature = ["..."]
-            .map(|values| values.iter().flat_map(|v| v.as_str()).collect::<Vec<_>>())
-            .unwrap_or_default();
-
-        params_to_add.retain(|p| !current_params.contains(&p.as_ref()));
-        params_to_remove.retain(|p| current_params.contains(&p.as_ref()));
-
-        // Describe changes that would be made
-        if !params_to_add.is_empty() {
-            params_to_add.sort_by(|a, b| feature_param_ordering(a.as_ref(), b.as_ref()));
-
-            for param in &params_to_add {
-                tracing::info!(?feature, ?param, "would add param");
-                output::shell_status(
-                    "Would add",
-                    &format!("{param:?} to package {pkg_name} feature {feature:?}"),
-                )?;
-            }
+    Ok(Some(manifest::FeatureEdit::Remove {
+        feature: feature.to_owned(),
+        reason: output::ChangeReason::Prune,
+    }))
+}
 
-            has_changes = true;
+/// Describes how to remove a feature aspect from a package, including all references to it from
+/// other features.
+///
+/// With `keep_feature`, e.g. for leaf features, the feature itself and the references to it from
+/// other features of the same package are kept, and only references to dependencies are removed.
+fn describe_removal(
+    ctx: &context::Context,
+    ws: &metadata::Workspace,
+    package: &cargo_metadata::Package,
+    keep_feature: bool,
+) -> Vec<manifest::FeatureEdit> {
+    let feature = ctx.local_feature_name(ws, &package.id);
+    let mut edits = Vec::new();
+    for (name, params) in &package.features {
+        if name == feature {
+            continue;
         }
 
+        let params_to_remove: Vec<String> = params
+            .iter()
+            .filter(|p| references_aspect(ctx, ws, package, p).is_some())
+            .filter(|p| !(keep_feature && *p == feature))
+            .cloned()
+            .collect();
         if !params_to_remove.is_empty() {
-            params_to_remove.sort_by(|a, b| feature_param_ordering(a.as_ref(), b.as_ref()));
-
-            for param in &params_to_remove {
-                tracing::info!(?feature, ?param, "would remove param");
-                output::shell_status(
-                    "Would remove",
-                    &format!("{param:?} from package {pkg_name} feature {feature:?}"),
-                )?;
-            }
-
-            has_changes = true;
-        }
-    } else {
-        let mut doc: toml_edit::DocumentMut = contents.parse()?;
-        // Not in dry-run/verify mode, let's make the changes
-        tracing::debug!(manifest_path=?package.manifest_path, "editing manifest file");
-
-        let features = doc.entry("features")
-            .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()))
-            .as_table_mut()
-            .ok_or_else(|| anyhow::anyhow!("failed to edit manifest for package `{}`: the `features` field exists but is not a table!", package.name))?;
-        let feature_arr = features.entry(feature.as_ref())
-            .or_insert_with(|| toml_edit::Item::Value(toml_edit::Value::Array(toml_edit::Array::new())))
-            .as_array_mut()
-            .ok_or_else(|| anyhow::anyhow!("failed to edit manifest for package `{}`: `features.{}` exists but is not an array!", package.name, feature))?;
-
-        params_to_add.retain(|param| {
-            !feature_arr
+            let reasons = params_to_remove
                 .iter()
-                .any(|p| p.as_str() == Some(param.as_ref()))
-        });
+                .map(|p| (p.clone(), output::ChangeReason::RemoveAspect))
+                .collect();
+            edits.push(manifest::FeatureEdit::Update(manifest::FeatureChanges {
+                feature: name.clone(),
+                params_to_add: Vec::new(),
+                params_to_remove,
+                reasons,
+                // Leave the order of features that aren't ours alone
+                sort: false,
+            }));
+        }
+    }
 
-        // As opposed to the verify case, we store the indices of what to remove, to aid in making
-        // edits as non-invasive as possible. If we don't, we might lose information like comments
-        // for existing feature params. It's a bit dirty to have side-effects in `retain()`, I hope
-        // you'll forgive me.
-        let mut param_indices_to_remove = Vec::new();
-        params_to_remove.retain(|param| {
-            if let Some(idx) = feature_arr
-                .iter()
-                .position(|p| p.as_str() == Some(param.as_ref()))
-            {
-                param_indices_to_remove.push(idx);
-                true
-            } else {
-                false
-            }
+    if package.features.contains_key(feature) && !keep_feature {
+        edits.push(manifest::FeatureEdit::Remove {
+            feature: feature.to_owned(),
+            reason: output::ChangeReason::RemoveAspect,
         });
+    }
 
-        if !(params_to_add.is_empty() && params_to_remove.is_empty()) {
-            // If sorting the existing array is disabled, at least sort the new stuff we're adding.
-            params_to_add.sort_by(|a, b| feature_param_ordering(a.as_ref(), b.as_ref()));
-            params_to_remove.sort_by(|a, b| feature_param_ordering(a.as_ref(), b.as_ref()));
-
-            for param in &params_to_add {
-                output::shell_status(
-                    "Adding",
-                    &format!("{param:?} to package {pkg_name} feature {feature:?}"),
-                )?;
-            }
-
-            for param in params_to_remove {
-                output::shell_status(
-                    "Removing",
-                    &format!("{param:?} from package {pkg_name} feature {feature:?}"),
-                )?;
-            }
-
-            // Now that we have logged what we're about to do, let's edit the actual TOML
+    edits
+}
 
-            // Reverse sort indices to make it safe to remove them one by one from the array without
-            // invalidating later indices
-            param_indices_to_remove.sort_by(|a, b| b.cmp(a));
+/// Describes how to rename a feature aspect, i.e. the one of the context, to `to` in a package,
+/// including all references to it from other features.
+///
+/// Packages that override the name of the aspect feature keep their feature, and only the key of
+/// the override is renamed.
+fn describe_rename(
+    ctx: &context::Context,
+    ws: &metadata::Workspace,
+    package: &cargo_metadata::Package,
+    to: &str,
+    alias: bool,
+) -> anyhow::Result<Vec<manifest::FeatureEdit>> {
+    let from = ctx.feature_name.as_ref();
+    let has_override = |id| {
+        ws.member_config(id)
+            .is_some_and(|c| c.feature_names.contains_key(from))
+    };
+    let renamed = |id| {
+        if has_override(id) {
+            ctx.local_feature_name(ws, id)
+        } else {
+            to
+        }
+    };
+    let mut edits = Vec::new();
+
+    for (name, params) in &package.features {
+        let replacements: Vec<(String, String)> = params
+            .iter()
+            .filter_map(|param| {
+                let id = references_aspect(ctx, ws, package, param)?;
+                let replacement = match param.split_once('/') {
+                    Some((dep_name, _)) => format!("{dep_name}/{}", renamed(id)),
+                    None => renamed(id).to_owned(),
+                };
+                (*param != replacement).then(|| (param.clone(), replacement))
+            })
+            .collect();
+
+        if !replacements.is_empty() {
+            edits.push(manifest::FeatureEdit::ReplaceParams {
+                feature: name.clone(),
+                replacements,
+            });
+        }
+    }
 
-            for &idx in &param_indices_to_remove {
-                // Remove in the actual TOML manifest
-                feature_arr.remove(idx);
-            }
+    if has_override(&package.id) {
+        if ws
+            .member_config(&package.id)
+            .is_some_and(|c| c.feature_names.contains_key(to))
+        {
+            anyhow::bail!(
+                "cannot rename the feature name override {from:?} to {to:?} in package {} since it already has an override for {to:?}",
+                package.name
+            );
+        }
 
-            for param in params_to_add {
-                feature_arr.push_formatted(toml_edit::Value::String(toml_edit::Formatted::new(
-                    param.into_owned(),
-                )));
-            }
+        edits.push(manifest::FeatureEdit::RenameFeatureName {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
+    } else if package.features.contains_key(from) {
+        if package.features.contains_key(to) {
+            anyhow::bail!(
+                "cannot rename feature {from:?} to {to:?} in package {} since it already has a feature named {to:?}",
+                package.name
+            );
+        }
 
-            if ctx.sort {
-                feature_arr.sort_by(|a, b| {
-                    feature_param_ordering(a.as_str().unwrap_or(""), b.as_str().unwrap_or(""))
-                });
-                feature_arr.fmt();
-            }
+        edits.push(manifest::FeatureEdit::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
 
-            fs::write(&package.manifest_path, doc.to_string())?;
+        // `publish = false` shows up as an empty list of registries
+        let is_published = package.publish.as_ref().is_none_or(|r| !r.is_empty());
+        if alias && is_published {
+            edits.push(manifest::FeatureEdit::Update(manifest::FeatureChanges {
+                feature: from.to_owned(),
+                params_to_add: vec![to.to_owned()],
+                params_to_remove: Vec::new(),
+                reasons: [(to.to_owned(), output::ChangeReason::RenameAspect)].into(),
+                sort: false,
+            }));
         }
     }
 
-    Ok(has_changes)
+    Ok(edits)
+}
+
+/// Whether a feature param of a package enables the feature aspect, either of the package itself
+/// (`enable-tracing`) or of one of its workspace dependencies (`foo/enable-tracing` or
+/// `foo?/enable-tracing`), using the local name of the aspect feature in the respective package.
+///
+/// Returns the package whose aspect feature is enabled.  External dependencies are never
+/// considered, since they might legitimately have a feature with the same name that we don't
+/// control.
+fn references_aspect<'w>(
+    ctx: &context::Context,
+    ws: &'w metadata::Workspace,
+    package: &'w cargo_metadata::Package,
+    param: &str,
+) -> Option<&'w cargo_metadata::PackageId> {
+    let Some((dep_name, dep_feature)) = param.split_once('/') else {
+        return (param == ctx.local_feature_name(ws, &package.id)).then_some(&package.id);
+    };
+    let dep_name = dep_name.strip_suffix('?').unwrap_or(dep_name);
+
+    package
+        .dependencies
+        .iter()
+        .filter(|d| metadata::local_name(d) == dep_name)
+        .filter_map(|d| ws.resolve_member(d))
+        .find(|&id| dep_feature == ctx.local_feature_name(ws, id))
 }
@@ -0,0 +1,833 @@
+use crate::{config, diff, output};
+use cargo_metadata::camino;
+use std::{cmp, collections, fs};
+
+/// An edit we would like to make to the features, or the feature aspect settings, of a manifest.
+pub enum FeatureEdit {
+    /// Adds and removes params of a feature, creating the feature if needed.
+    Update(FeatureChanges),
+    /// Removes a feature entirely, if it exists.
+    Remove {
+        feature: String,
+        reason: output::ChangeReason,
+    },
+    /// Renames a feature, if it exists, keeping its position and formatting.
+    Rename { from: String, to: String },
+    /// Replaces params of a feature in place, keeping their position and formatting.
+    ReplaceParams {
+        feature: String,
+        replacements: Vec<(String, String)>,
+    },
+    /// Renames the feature aspect that an override in `[package.metadata.feature-aspect]
+    /// feature-names` applies to, if it exists, keeping its position and formatting.
+    RenameFeatureName { from: String, to: String },
+    /// Renames a feature aspect declared in `[workspace.metadata.feature-aspect]`, if it exists,
+    /// along with its leaf features that have the old name.
+    RenameAspectSettings { from: String, to: String },
+}
+
+/// The changes we would like to make to a single feature of a package manifest.
+pub struct FeatureChanges {
+    pub feature: String,
+    pub params_to_add: Vec<String>,
+    pub params_to_remove: Vec<String>,
+    /// Why each param is added or removed, for reporting.
+    pub reasons: collections::BTreeMap<String, output::ChangeReason>,
+    /// Whether to sort the whole feature param array after editing it.
+    pub sort: bool,
+}
+
+/// Applies the changes for all feature aspects of a single package to its manifest file.
+///
+/// The manifest is read and parsed once, all changes are applied to the in-memory document, and
+/// the result is written back at most once.  If `write` is false, the changes are only reported,
+/// both as human-readable messages and with the given reporter.  If `diff_root` is given, a
+/// unified diff of the manifest is printed as well, with the path of the manifest relative to
+/// that directory.
+///
+/// Returns true if actual changes compared to the physical manifest file were detected.
+pub fn apply_changes(
+    package: &cargo_metadata::Package,
+    edits: Vec<FeatureEdit>,
+    write: bool,
+    diff_root: Option<&camino::Utf8Path>,
+    reporter: &mut output::Reporter,
+) -> anyhow::Result<bool> {
+    apply_manifest_changes(
+        Some(&package.name),
+        &package.manifest_path,
+        edits,
+        write,
+        diff_root,
+        reporter,
+    )
+}
+
+/// Applies changes to the root manifest of a virtual workspace, i.e. one that doesn't belong to a
+/// package, like [`apply_changes`].  Only the settings in `[workspace.metadata]` can be edited.
+pub fn apply_workspace_changes(
+    manifest_path: &camino::Utf8Path,
+    edits: Vec<FeatureEdit>,
+    write: bool,
+    diff_root: Option<&camino::Utf8Path>,
+    reporter: &mut output::Reporter,
+) -> anyhow::Result<bool> {
+    apply_manifest_changes(None, manifest_path, edits, write, diff_root, reporter)
+}
+
+fn apply_manifest_changes(
+    package_name: Option<&str>,
+    manifest_path: &camino::Utf8Path,
+    edits: Vec<FeatureEdit>,
+    write: bool,
+    diff_root: Option<&camino::Utf8Path>,
+    reporter: &mut output::Reporter,
+) -> anyhow::Result<bool> {
+    let contents = fs::read_to_string(manifest_path)?;
+    let manifest = Manifest::parse(
+        package_name,
+        manifest_path,
+        &contents,
+        write,
+        Some(reporter),
+    )?;
+    let (doc, has_changes) = manifest.edit(edits)?;
+
+    // Edits that cancel each other out would otherwise result in an empty diff
+    let new_contents = doc.to_string();
+    if let Some(root) = diff_root.filter(|_| has_changes && new_contents != contents) {
+        let path = manifest_path.strip_prefix(root).unwrap_or(manifest_path);
+        diff::print_unified_diff(path.as_str(), &contents, &new_contents)?;
+    }
+
+    if has_changes && write {
+        tracing::debug!(?manifest_path, "writing manifest file");
+        fs::write(manifest_path, new_contents)?;
+    }
+
+    Ok(has_changes)
+}
+
+/// Determines whether the changes for a single package would change its manifest file, without
+/// reporting anything.
+pub fn would_change(
+    package: &cargo_metadata::Package,
+    edits: Vec<FeatureEdit>,
+) -> anyhow::Result<bool> {
+    let contents = fs::read_to_string(&package.manifest_path)?;
+    let manifest = Manifest::parse(
+        Some(&package.name),
+        &package.manifest_path,
+        &contents,
+        false,
+        None,
+    )?;
+    let (_, has_changes) = manifest.edit(edits)?;
+    Ok(has_changes)
+}
+
+/// Edits a single feature in the manifest document, logging what is (or would be) changed.
+fn edit_feature(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    changes: FeatureChanges,
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let pkg_name = manifest.package_name()?;
+    // Here we do lots of `Vec::contains` but since these are small vecs, it is not worth it
+    // to do some fancy hash set stuff, since hashing all the strings will probably take more
+    // time than just traversing the vec.
+
+    let FeatureChanges {
+        feature,
+        mut params_to_add,
+        mut params_to_remove,
+        reasons,
+        sort,
+    } = changes;
+
+    let features = doc.entry("features")
+        .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| anyhow::anyhow!("failed to edit manifest for package `{}`: the `features` field exists but is not a table!", pkg_name))?;
+    let is_new_feature = !features.contains_key(&feature);
+    let feature_arr = features.entry(&feature)
+        .or_insert_with(|| toml_edit::Item::Value(toml_edit::Value::Array(toml_edit::Array::new())))
+        .as_array_mut()
+        .ok_or_else(|| anyhow::anyhow!("failed to edit manifest for package `{}`: `features.{}` exists but is not an array!", pkg_name, feature))?;
+
+    // The same param might have been scheduled for removal for several reasons
+    params_to_remove.sort();
+    params_to_remove.dedup();
+
+    params_to_add.retain(|param| !feature_arr.iter().any(|p| p.as_str() == Some(param)));
+
+    // We store the indices of what to remove, to aid in making edits as non-invasive as possible.
+    // If we don't, we might lose information like comments for existing feature params. It's a
+    // bit dirty to have side-effects in `retain()`, I hope you'll forgive me.
+    let mut param_indices_to_remove = Vec::new();
+    params_to_remove.retain(|param| {
+        if let Some(idx) = feature_arr.iter().position(|p| p.as_str() == Some(param)) {
+            param_indices_to_remove.push(idx);
+            true
+        } else {
+            false
+        }
+    });
+
+    if params_to_add.is_empty() && params_to_remove.is_empty() {
+        // Packages at the bottom of a downward feature aspect get an empty feature
+        if is_new_feature {
+            let status = if write { "Adding" } else { "Would add" };
+            tracing::info!(?feature, "adding empty feature");
+            manifest.status(
+                status,
+                &format!("feature {feature:?} to package {pkg_name}"),
+            )?;
+            manifest.report_change(
+                &feature,
+                None,
+                None,
+                output::ChangeAction::AddFeature,
+                output::ChangeReason::InScope,
+            )?;
+        }
+        return Ok(is_new_feature);
+    }
+
+    // If sorting the existing array is disabled, at least sort the new stuff we're adding.
+    params_to_add.sort_by(|a, b| feature_param_ordering(a, b));
+    params_to_add.dedup();
+    params_to_remove.sort_by(|a, b| feature_param_ordering(a, b));
+
+    let (add_status, remove_status) = if write {
+        ("Adding", "Removing")
+    } else {
+        ("Would add", "Would remove")
+    };
+
+    for param in &params_to_add {
+        tracing::info!(?feature, ?param, "adding param");
+        manifest.status(
+            add_status,
+            &format!("{param:?} to package {pkg_name} feature {feature:?}"),
+        )?;
+        manifest.report_change(
+            &feature,
+            Some(param),
+            None,
+            output::ChangeAction::Add,
+            param_reason(&reasons, param),
+        )?;
+    }
+
+    for param in &params_to_remove {
+        tracing::info!(?feature, ?param, "removing param");
+        manifest.status(
+            remove_status,
+            &format!("{param:?} from package {pkg_name} feature {feature:?}"),
+        )?;
+        manifest.report_change(
+            &feature,
+            Some(param),
+            None,
+            output::ChangeAction::Remove,
+            param_reason(&reasons, param),
+        )?;
+    }
+
+    // Now that we have logged what we're about to do, let's edit the actual TOML
+
+    // Reverse sort indices to make it safe to remove them one by one from the array without
+    // invalidating later indices
+    param_indices_to_remove.sort_by(|a, b| b.cmp(a));
+
+    for &idx in &param_indices_to_remove {
+        let removed = feature_arr.remove(idx);
+
+        // The first element is formatted differently, so when removing it, the next element has
+        // to take over its formatting, e.g. to not end up with `[ "b"]`.
+        if idx == 0 {
+            if let Some(first) = feature_arr.get_mut(0) {
+                let prefix = removed.decor().prefix().cloned().unwrap_or_default();
+                first.decor_mut().set_prefix(prefix);
+            }
+        }
+    }
+
+    for param in params_to_add {
+        push_param(feature_arr, param);
+    }
+
+    if sort {
+        feature_arr.sort_by(|a, b| {
+            feature_param_ordering(a.as_str().unwrap_or(""), b.as_str().unwrap_or(""))
+        });
+        feature_arr.fmt();
+    }
+
+    Ok(true)
+}
+
+/// Removes a feature from the manifest document, logging what is (or would be) changed.
+///
+/// The whole `[features]` table is removed if it ends up empty.
+fn remove_feature(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    feature: &str,
+    reason: output::ChangeReason,
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let pkg_name = manifest.package_name()?;
+    let Some(features) = doc.get_mut("features").and_then(|f| f.as_table_like_mut()) else {
+        return Ok(false);
+    };
+    if !features.contains_key(feature) {
+        return Ok(false);
+    }
+
+    let status = if write { "Removing" } else { "Would remove" };
+    tracing::info!(?feature, "removing feature");
+    manifest.status(
+        status,
+        &format!("feature {feature:?} from package {pkg_name}"),
+    )?;
+    manifest.report_change(
+        feature,
+        None,
+        None,
+        output::ChangeAction::RemoveFeature,
+        reason,
+    )?;
+
+    features.remove(feature);
+    // Leaf features are never removed unless asked for, so the table only ends up empty if it held
+    // nothing but aspect features
+    if features.is_empty() {
+        doc.remove("features");
+    }
+
+    Ok(true)
+}
+
+/// Renames a feature in the manifest document, logging what is (or would be) changed.
+fn rename_feature(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let pkg_name = manifest.package_name()?;
+    let Some(features) = doc.get_mut("features") else {
+        return Ok(false);
+    };
+    let contains_key = |key| {
+        features
+            .as_table_like()
+            .is_some_and(|f| f.contains_key(key))
+    };
+    if !contains_key(from) {
+        return Ok(false);
+    }
+    if contains_key(to) {
+        anyhow::bail!("failed to edit manifest for package `{pkg_name}`: cannot rename feature {from:?} since `features.{to}` already exists!");
+    }
+
+    let status = if write { "Renaming" } else { "Would rename" };
+    tracing::info!(?from, ?to, "renaming feature");
+    manifest.status(
+        status,
+        &format!("feature {from:?} to {to:?} in package {pkg_name}"),
+    )?;
+    manifest.report_change(
+        from,
+        None,
+        Some(to),
+        output::ChangeAction::RenameFeature,
+        output::ChangeReason::RenameAspect,
+    )?;
+
+    rename_key(features, from, to);
+
+    Ok(true)
+}
+
+/// Renames the key of an override in `[package.metadata.feature-aspect] feature-names` in the
+/// manifest document, logging what is (or would be) changed.
+fn rename_feature_name(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let pkg_name = manifest.package_name()?;
+    let Some(feature_names) = doc
+        .get_mut("package")
+        .and_then(|p| p.get_mut("metadata"))
+        .and_then(|m| m.get_mut(config::METADATA_KEY))
+        .and_then(|f| f.get_mut("feature-names"))
+        .filter(|n| n.as_table_like().is_some_and(|n| n.contains_key(from)))
+    else {
+        return Ok(false);
+    };
+
+    let status = if write { "Renaming" } else { "Would rename" };
+    tracing::info!(?from, ?to, "renaming feature name override");
+    manifest.status(
+        status,
+        &format!("feature name override {from:?} to {to:?} in package {pkg_name}"),
+    )?;
+    manifest.report_change_at(
+        manifest.feature_name_line(from),
+        from,
+        None,
+        Some(to),
+        output::ChangeAction::RenameSetting,
+        output::ChangeReason::RenameAspect,
+    )?;
+
+    rename_key(feature_names, from, to);
+
+    Ok(true)
+}
+
+/// Renames a feature aspect declared in `[workspace.metadata.feature-aspect]` in the manifest
+/// document, along with its leaf features that have the old name, logging what is (or would be)
+/// changed.
+///
+/// An aspect with a `name` keeps its key, so that only the `name` is updated.
+fn rename_aspect_settings(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let Some(aspects) = doc
+        .get_mut("workspace")
+        .and_then(|w| w.get_mut("metadata"))
+        .and_then(|m| m.get_mut(config::METADATA_KEY))
+    else {
+        return Ok(false);
+    };
+    let Some(key) = aspects.as_table_like().and_then(|aspects| {
+        aspects
+            .iter()
+            .find(|(key, aspect)| {
+                aspect.get("name").and_then(|n| n.as_str()).unwrap_or(key) == from
+            })
+            .map(|(key, _)| key.to_owned())
+    }) else {
+        return Ok(false);
+    };
+    let Some(aspect) = aspects.get_mut(key.as_str()) else {
+        return Ok(false);
+    };
+    let has_name = aspect.get("name").is_some();
+    if !has_name && manifest.original_aspect(to).is_some() {
+        anyhow::bail!("failed to edit manifest `{}`: cannot rename feature aspect {from:?} since `workspace.metadata.{}.{to}` already exists!", manifest.path, config::METADATA_KEY);
+    }
+
+    let (status, replace_status) = if write {
+        ("Renaming", "Replacing")
+    } else {
+        ("Would rename", "Would replace")
+    };
+    tracing::info!(?from, ?to, "renaming feature aspect settings");
+    manifest.status(
+        status,
+        &format!("feature aspect {from:?} to {to:?} in the workspace metadata"),
+    )?;
+    manifest.report_change_at(
+        manifest.aspect_line(&key, None),
+        from,
+        None,
+        Some(to),
+        output::ChangeAction::RenameSetting,
+        output::ChangeReason::RenameAspect,
+    )?;
+
+    if let Some(name) = aspect.get_mut("name").and_then(|n| n.as_value_mut()) {
+        let decor = name.decor().clone();
+        *name = toml_edit::Value::from(to);
+        *name.decor_mut() = decor;
+    }
+
+    if let Some(leaf_features) = aspect
+        .get_mut("leaf-features")
+        .and_then(|l| l.as_array_mut())
+    {
+        for value in leaf_features.iter_mut() {
+            let Some(leaf_feature) = value.as_str() else {
+                continue;
+            };
+            let replacement = match leaf_feature.split_once('/') {
+                Some((package, feature)) if feature == from => format!("{package}/{to}"),
+                None if leaf_feature == from => to.to_owned(),
+                _ => continue,
+            };
+            let leaf_feature = leaf_feature.to_owned();
+
+            tracing::info!(?leaf_feature, ?replacement, "replacing leaf feature");
+            manifest.status(
+                replace_status,
+                &format!("leaf feature {leaf_feature:?} with {replacement:?} of feature aspect {from:?} in the workspace metadata"),
+            )?;
+            manifest.report_change_at(
+                manifest.aspect_line(&key, Some(&leaf_feature)),
+                from,
+                Some(&leaf_feature),
+                Some(&replacement),
+                output::ChangeAction::RenameSetting,
+                output::ChangeReason::RenameAspect,
+            )?;
+
+            let decor = value.decor().clone();
+            *value = toml_edit::Value::from(replacement);
+            *value.decor_mut() = decor;
+        }
+    }
+
+    if !has_name {
+        rename_key(aspects, &key, to);
+    }
+
+    Ok(true)
+}
+
+/// Renames a key of a table or inline table in place, carrying over the formatting (including
+/// comments) of the key.
+fn rename_key(table: &mut toml_edit::Item, from: &str, to: &str) {
+    let rename = |key: toml_edit::Key| {
+        if key.get() == from {
+            toml_edit::Key::new(to).with_leaf_decor(key.leaf_decor().clone())
+        } else {
+            key
+        }
+    };
+
+    // Keys of a table can't be renamed in place, so re-insert all entries in their original order
+    match table {
+        toml_edit::Item::Table(table) => {
+            let keys: Vec<String> = table.iter().map(|(key, _)| key.to_owned()).collect();
+            for key in keys {
+                if let Some((key, item)) = table.remove_entry(&key) {
+                    table.insert_formatted(&rename(key), item);
+                }
+            }
+        }
+        toml_edit::Item::Value(toml_edit::Value::InlineTable(table)) => {
+            let keys: Vec<String> = table.iter().map(|(key, _)| key.to_owned()).collect();
+            for key in keys {
+                if let Some((key, value)) = table.remove_entry(&key) {
+                    table.insert_formatted(&rename(key), value);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces params of a feature in the manifest document, logging what is (or would be) changed.
+fn replace_params(
+    doc: &mut toml_edit::DocumentMut,
+    manifest: &mut Manifest,
+    feature: &str,
+    replacements: &[(String, String)],
+) -> anyhow::Result<bool> {
+    let write = manifest.write;
+    let pkg_name = manifest.package_name()?;
+    let Some(feature_arr) = doc
+        .get_mut("features")
+        .and_then(|f| f.get_mut(feature))
+        .and_then(|f| f.as_array_mut())
+    else {
+        return Ok(false);
+    };
+
+    let status = if write { "Replacing" } else { "Would replace" };
+    let mut has_changes = false;
+    for value in feature_arr.iter_mut() {
+        let Some((from, to)) = replacements
+            .iter()
+            .find(|(from, _)| value.as_str() == Some(from))
+        else {
+            continue;
+        };
+
+        tracing::info!(?feature, ?from, ?to, "replacing param");
+        manifest.status(
+            status,
+            &format!("{from:?} with {to:?} in package {pkg_name} feature {feature:?}"),
+        )?;
+        manifest.report_change(
+            feature,
+            Some(from),
+            Some(to),
+            output::ChangeAction::Replace,
+            output::ChangeReason::RenameAspect,
+        )?;
+
+        let decor = value.decor().clone();
+        *value = toml_edit::Value::from(to.as_str());
+        *value.decor_mut() = decor;
+        has_changes = true;
+    }
+
+    Ok(has_changes)
+}
+
+/// A manifest file that is being edited.
+struct Manifest<'a> {
+    /// The package of the manifest, or `None` for the root manifest of a virtual workspace.
+    package_name: Option<&'a str>,
+    path: &'a camino::Utf8Path,
+    /// The manifest as it was read from disk.
+    original: toml_edit::ImDocument<&'a str>,
+    /// Whether the changes will be written back, as opposed to `--dry-run` or `--verify`.
+    write: bool,
+    /// Where to report changes, or `None` to not report them at all.
+    reporter: Option<&'a mut output::Reporter>,
+}
+
+impl<'a> Manifest<'a> {
+    fn parse(
+        package_name: Option<&'a str>,
+        path: &'a camino::Utf8Path,
+        contents: &'a str,
+        write: bool,
+        reporter: Option<&'a mut output::Reporter>,
+    ) -> anyhow::Result<Self> {
+        // The original document keeps the spans that are needed for reporting line numbers, which
+        // are lost when editing.
+        Ok(Self {
+            package_name,
+            path,
+            original: toml_edit::ImDocument::parse(contents)?,
+            write,
+            reporter,
+        })
+    }
+
+    /// Applies changes to the manifest in memory, returning the edited document and whether there
+    /// were any changes.
+    fn edit(mut self, edits: Vec<FeatureEdit>) -> anyhow::Result<(toml_edit::DocumentMut, bool)> {
+        // We need to parse the actual manifest file instead of looking at `package.features`, since
+        // cargo auto-generates implicit features at runtime.
+        let mut doc = self.original.clone().into_mut();
+        let mut has_changes = false;
+
+        for edit in edits {
+            has_changes |= match edit {
+                FeatureEdit::Update(changes) => edit_feature(&mut doc, &mut self, changes)?,
+                FeatureEdit::Remove { feature, reason } => {
+                    remove_feature(&mut doc, &mut self, &feature, reason)?
+                }
+                FeatureEdit::Rename { from, to } => {
+                    rename_feature(&mut doc, &mut self, &from, &to)?
+                }
+                FeatureEdit::ReplaceParams {
+                    feature,
+                    replacements,
+                } => replace_params(&mut doc, &mut self, &feature, &replacements)?,
+                FeatureEdit::RenameFeatureName { from, to } => {
+                    rename_feature_name(&mut doc, &mut self, &from, &to)?
+                }
+                FeatureEdit::RenameAspectSettings { from, to } => {
+                    rename_aspect_settings(&mut doc, &mut self, &from, &to)?
+                }
+            };
+        }
+
+        Ok((doc, has_changes))
+    }
+
+    /// The name of the package of the manifest, for edits that only make sense for packages.
+    fn package_name(&self) -> anyhow::Result<&'a str> {
+        self.package_name.ok_or_else(|| {
+            anyhow::anyhow!(
+                "failed to edit manifest `{}`: it is the manifest of a virtual workspace, which has no features!",
+                self.path
+            )
+        })
+    }
+
+    /// Prints a styled action message about a change, unless changes are not reported.
+    fn status(&self, action: &str, message: &str) -> anyhow::Result<()> {
+        if self.reporter.is_none() {
+            return Ok(());
+        }
+        output::shell_status(action, message)
+    }
+
+    /// Reports a change to a feature for `--message-format`.
+    fn report_change(
+        &mut self,
+        feature: &str,
+        param: Option<&str>,
+        replacement: Option<&str>,
+        action: output::ChangeAction,
+        reason: output::ChangeReason,
+    ) -> anyhow::Result<()> {
+        let line = self.line(feature, param);
+        self.report_change_at(line, feature, param, replacement, action, reason)
+    }
+
+    /// Reports a change about the given line for `--message-format`.
+    fn report_change_at(
+        &mut self,
+        line: Option<usize>,
+        feature: &str,
+        param: Option<&str>,
+        replacement: Option<&str>,
+        action: output::ChangeAction,
+        reason: output::ChangeReason,
+    ) -> anyhow::Result<()> {
+        let Some(reporter) = self.reporter.as_deref_mut() else {
+            return Ok(());
+        };
+        reporter.report_change(&output::Change {
+            package: self.package_name,
+            manifest_path: self.path.as_str(),
+            line,
+            feature,
+            param,
+            replacement,
+            action,
+            reason,
+            applied: self.write,
+        })
+    }
+
+    /// The line in the original manifest that a change is about: the param if it exists, or else
+    /// the feature entry if it exists, or else the `[features]` table.
+    fn line(&self, feature: &str, param: Option<&str>) -> Option<usize> {
+        let features = self.original.as_table().get("features")?;
+        let span = match features
+            .as_table_like()
+            .and_then(|f| f.get_key_value(feature))
+        {
+            Some((key, item)) => param
+                .and_then(|param| {
+                    let arr = item.as_array()?;
+                    arr.iter().find(|p| p.as_str() == Some(param))?.span()
+                })
+                .or_else(|| key.span()),
+            None => match features.as_table() {
+                // The span of a table is its header
+                Some(table) => table.span(),
+                None => features.span(),
+            },
+        }?;
+
+        Some(self.line_at(span.start))
+    }
+
+    /// The line in the original manifest of the override for a feature aspect in
+    /// `[package.metadata.feature-aspect] feature-names`.
+    fn feature_name_line(&self, aspect: &str) -> Option<usize> {
+        let (key, _) = self
+            .original
+            .get("package")?
+            .get("metadata")?
+            .get(config::METADATA_KEY)?
+            .get("feature-names")?
+            .as_table_like()?
+            .get_key_value(aspect)?;
+        Some(self.line_at(key.span()?.start))
+    }
+
+    /// A feature aspect in `[workspace.metadata.feature-aspect]` of the original manifest, by key.
+    fn original_aspect(&self, key: &str) -> Option<(&toml_edit::Key, &toml_edit::Item)> {
+        self.original
+            .get("workspace")?
+            .get("metadata")?
+            .get(config::METADATA_KEY)?
+            .as_table_like()?
+            .get_key_value(key)
+    }
+
+    /// The line in the original manifest of a feature aspect in
+    /// `[workspace.metadata.feature-aspect]`: the given leaf feature, or else its `name` if it has
+    /// one, or else its key.
+    fn aspect_line(&self, key: &str, leaf_feature: Option<&str>) -> Option<usize> {
+        let (key, aspect) = self.original_aspect(key)?;
+        let span = match leaf_feature {
+            Some(leaf_feature) => aspect
+                .get("leaf-features")?
+                .as_array()?
+                .iter()
+                .find(|l| l.as_str() == Some(leaf_feature))?
+                .span(),
+            // The span of a table is its header
+            None => aspect
+                .get("name")
+                .and_then(|n| n.span())
+                .or_else(|| key.span())
+                .or_else(|| aspect.span()),
+        }?;
+        Some(self.line_at(span.start))
+    }
+
+    /// The line in the original manifest that contains a byte offset.
+    fn line_at(&self, offset: usize) -> usize {
+        self.original.raw()[..offset].matches('\n').count() + 1
+    }
+}
+
+fn param_reason(
+    reasons: &collections::BTreeMap<String, output::ChangeReason>,
+    param: &str,
+) -> output::ChangeReason {
+    // Every param should have a reason, but an unknown reason is not worth failing over
+    reasons.get(param).copied().unwrap_or_else(|| {
+        tracing::debug!(?param, "no reason recorded for param");
+        output::ChangeReason::InScope
+    })
+}
+
+/// Appends a param to a feature array, continuing the layout of arrays that have one element per
+/// line, including keeping a comment after the last element on that element's line.
+fn push_param(feature_arr: &mut toml_edit::Array, param: String) {
+    let mut value = toml_edit::Value::from(param);
+
+    let indent = feature_arr
+        .iter()
+        .last()
+        .and_then(|last| last.decor().prefix()?.as_str())
+        .filter(|prefix| prefix.contains('\n'))
+        .map(str::to_owned);
+    if let Some(indent) = indent {
+        let mut trailing = feature_arr.trailing().as_str().unwrap_or("").to_owned();
+        // Without a trailing comma, whatever follows the last element is part of its decor
+        if !feature_arr.trailing_comma() {
+            if let Some(last) = feature_arr.get_mut(feature_arr.len() - 1) {
+                let suffix = last.decor().suffix().and_then(|s| s.as_str()).unwrap_or("");
+                trailing = format!("{suffix}{trailing}");
+                last.decor_mut().set_suffix("");
+            }
+        }
+        let (comment, rest) = trailing.split_at(trailing.rfind('\n').unwrap_or(0));
+        value.decor_mut().set_prefix(format!("{comment}{indent}"));
+        feature_arr.set_trailing(rest);
+        feature_arr.set_trailing_comma(true);
+    }
+
+    feature_arr.push_formatted(value);
+}
+
+// Awkward sorting functions because `.sort_by_key()` doesn't handle sort keys with lifetimes nicely
+fn feature_param_sort_key(param: &str) -> (bool, &str) {
+    if param.starts_with("dep:") {
+        (false, param)
+    } else {
+        (true, param)
+    }
+}
+
+fn feature_param_ordering(a: &str, b: &str) -> cmp::Ordering {
+    feature_param_sort_key(a).cmp(&feature_param_sort_key(b))
+}
@@ -1,3 +1,5 @@
+use crate::config;
+use cargo_metadata::camino;
 use std::{collections, path};
 
 pub fn resolve_ws(
@@ -22,14 +24,140 @@ pub fn resolve_ws(
     let ws = cmd.exec().or_else(|_| {
         cmd.no_deps();
         cmd.exec()
-    })?;
-    Ok(ws)
+    });
+    match ws {
+        Ok(ws) => Ok(ws),
+        // Cargo refuses to load manifests with params that forward to dependencies that were
+        // removed, so we never get to remove those params ourselves
+        Err(e) if e.to_string().contains("is not a dependency") => {
+            Err(anyhow::Error::new(e).context(
+                "a feature forwards to a dependency that doesn't exist, e.g. because it was removed; remove the param by hand, since the workspace can't be loaded until then",
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The members of a workspace, along with what is needed to resolve their dependencies.
+pub struct Workspace {
+    /// The root directory of the workspace.
+    pub root: camino::Utf8PathBuf,
+    pub members: Vec<cargo_metadata::Package>,
+    /// Packages in the resolved dependency graph that are not workspace members, e.g. crates from
+    /// crates.io or git repositories.
+    pub externals: Vec<cargo_metadata::Package>,
+    /// Maps the package ID of each workspace member to its index in `members`.
+    member_ids: collections::HashMap<cargo_metadata::PackageId, usize>,
+    /// Maps the directory of each workspace member to its package ID.
+    member_dirs: collections::HashMap<camino::Utf8PathBuf, cargo_metadata::PackageId>,
+    /// Maps the package ID of each external package to its index in `externals`.
+    external_ids: collections::HashMap<cargo_metadata::PackageId, usize>,
+    /// Maps each package ID to the package IDs of its resolved dependencies.  This is empty if we
+    /// fell back to resolving the workspace without dependencies.
+    resolved_deps: collections::HashMap<cargo_metadata::PackageId, Vec<cargo_metadata::PackageId>>,
+    /// The settings from `[package.metadata.feature-aspect]` of each workspace member.
+    member_configs: collections::HashMap<cargo_metadata::PackageId, config::PackageConfig>,
+}
+
+impl Workspace {
+    pub fn new(ws: cargo_metadata::Metadata) -> anyhow::Result<Self> {
+        let workspace_members: collections::HashSet<_> = ws.workspace_members.iter().collect();
+        let (members, externals): (Vec<_>, Vec<_>) = ws
+            .packages
+            .into_iter()
+            .partition(|p| workspace_members.contains(&p.id));
+        let member_ids = members
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (p.id.clone(), idx))
+            .collect();
+        let member_dirs = members
+            .iter()
+            .filter_map(|p| Some((p.manifest_path.parent()?.to_owned(), p.id.clone())))
+            .collect();
+        let external_ids = externals
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (p.id.clone(), idx))
+            .collect();
+        let resolved_deps = ws
+            .resolve
+            .map(|resolve| {
+                resolve
+                    .nodes
+                    .into_iter()
+                    .map(|node| (node.id, node.deps.into_iter().map(|d| d.pkg).collect()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let member_configs = members
+            .iter()
+            .map(|p| Ok((p.id.clone(), config::package_config(p)?)))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            root: ws.workspace_root,
+            members,
+            externals,
+            member_ids,
+            member_dirs,
+            external_ids,
+            resolved_deps,
+            member_configs,
+        })
+    }
+
+    /// Returns the workspace member that a dependency refers to, if any.
+    ///
+    /// Dependencies are resolved by their source path, so that a crate from a registry never gets
+    /// confused with a workspace member that happens to have the same name.
+    pub fn resolve_member(
+        &self,
+        dependency: &cargo_metadata::Dependency,
+    ) -> Option<&cargo_metadata::PackageId> {
+        self.member_dirs.get(dependency.path.as_ref()?)
+    }
+
+    /// Returns the package, workspace member or external, that a dependency of a workspace member
+    /// refers to, if it could be resolved.
+    pub fn resolve_dependency(
+        &self,
+        package: &cargo_metadata::Package,
+        dependency: &cargo_metadata::Dependency,
+    ) -> Option<&cargo_metadata::PackageId> {
+        if let Some(id) = self.resolve_member(dependency) {
+            return Some(id);
+        }
+
+        // The resolve graph doesn't tell us which dependency declaration was resolved to which
+        // package, so match them up by name and version requirement.
+        self.resolved_deps.get(&package.id)?.iter().find(|id| {
+            self.external(id)
+                .is_some_and(|p| p.name == dependency.name && dependency.req.matches(&p.version))
+        })
+    }
+
+    /// The settings from `[package.metadata.feature-aspect]` of a workspace member.
+    pub fn member_config(&self, id: &cargo_metadata::PackageId) -> Option<&config::PackageConfig> {
+        self.member_configs.get(id)
+    }
+
+    /// Looks up a workspace member by its ID.
+    pub fn member(&self, id: &cargo_metadata::PackageId) -> Option<&cargo_metadata::Package> {
+        self.member_ids.get(id).map(|&idx| &self.members[idx])
+    }
+
+    /// Looks up a package that is not a workspace member by its ID.
+    pub fn external(&self, id: &cargo_metadata::PackageId) -> Option<&cargo_metadata::Package> {
+        self.external_ids.get(id).map(|&idx| &self.externals[idx])
+    }
 }
 
-pub fn find_ws_members(ws: cargo_metadata::Metadata) -> Vec<cargo_metadata::Package> {
-    let workspace_members: collections::HashSet<_> = ws.workspace_members.iter().collect();
-    ws.packages
-        .into_iter()
-        .filter(|p| workspace_members.contains(&p.id))
-        .collect()
+/// The name that a dependency is referred to by in the manifest of the depending package, which
+/// is what feature params like `dep/feature` need to use.
+///
+/// This differs from the package name of the dependency if it is renamed, e.g. with
+/// `log2 = { package = "logging", ... }`.
+pub fn local_name(dependency: &cargo_metadata::Dependency) -> &str {
+    dependency.rename.as_deref().unwrap_or(&dependency.name)
 }
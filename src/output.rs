@@ -1,3 +1,5 @@
+use crate::cli;
+
 /// Whether to color logged output
 pub fn colorize_stderr() -> termcolor::ColorChoice {
     if concolor_control::get(concolor_control::Stream::Stderr).color() {
@@ -44,3 +46,321 @@ pub fn shell_print(
 pub fn shell_status(action: &str, message: &str) -> anyhow::Result<()> {
     shell_print(action, message, termcolor::Color::Green, true)
 }
+
+/// Print a warning message.
+pub fn shell_warn(message: &str) -> anyhow::Result<()> {
+    shell_print("warning", message, termcolor::Color::Yellow, false)
+}
+
+/// Reports changes in the selected message format, in addition to the human-readable messages.
+pub struct Reporter {
+    format: cli::MessageFormat,
+    /// Whether changes are failures, as opposed to suggestions or applied changes.
+    verify: bool,
+    /// SARIF results are collected and printed as a single log with the summary.
+    sarif_results: Vec<serde_json::Value>,
+}
+
+/// A change to a manifest, as reported with `--message-format json`.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename = "change")]
+pub struct Change<'a> {
+    /// The package of the manifest, or `None` for the root manifest of a virtual workspace.
+    pub package: Option<&'a str>,
+    pub manifest_path: &'a str,
+    /// The line in the manifest that the change is about, if any.
+    pub line: Option<usize>,
+    pub feature: &'a str,
+    /// The param that is added, removed or replaced, if the change is about a single param.
+    pub param: Option<&'a str>,
+    /// The new name of a renamed feature or replaced param.
+    pub replacement: Option<&'a str>,
+    pub action: ChangeAction,
+    pub reason: ChangeReason,
+    /// Whether the change was written to the manifest, as opposed to `--dry-run` or `--verify`.
+    pub applied: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeAction {
+    Add,
+    Remove,
+    Replace,
+    AddFeature,
+    RemoveFeature,
+    RenameFeature,
+    RenameSetting,
+}
+
+/// Why a change is made to a manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeReason {
+    /// The package is in scope of the feature aspect, so it needs the feature.
+    InScope,
+    /// The param propagates the feature aspect to an in-scope dependency.
+    Dependency,
+    /// The param enables a leaf feature of the package itself.
+    LeafFeature,
+    /// The param was given with `--add-feature-param`.
+    ExtraParam,
+    /// The param forwards the feature aspect to a dependency with or without `?`, but the
+    /// dependency is respectively required or optional.
+    OptionalDependency,
+    /// The param forwards the feature aspect to a dependency that is no longer in scope.
+    Stale,
+    /// The feature aspect is included in or excluded from the `default` feature.
+    DefaultFeature,
+    /// The package is no longer in scope, and `--prune` was passed.
+    Prune,
+    /// The feature aspect is removed from the workspace.
+    RemoveAspect,
+    /// The feature aspect is renamed in the workspace.
+    RenameAspect,
+}
+
+/// The outcome of a run, as reported with `--message-format json` after all changes.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type", rename = "summary")]
+pub struct Summary<'a> {
+    pub changed_packages: Vec<&'a str>,
+    pub dry_run: bool,
+    pub verify: bool,
+    /// False if `--verify` was passed and changes were detected.
+    pub success: bool,
+}
+
+impl ChangeAction {
+    /// The ID and description of the SARIF rule for the action.
+    fn rule(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Add => (
+                "missing-param",
+                "A feature is missing a param of a feature aspect",
+            ),
+            Self::Remove => (
+                "stale-param",
+                "A feature has a stale param of a feature aspect",
+            ),
+            Self::Replace => (
+                "renamed-param",
+                "A param refers to a renamed feature aspect",
+            ),
+            Self::AddFeature => ("missing-feature", "A package is missing a feature aspect"),
+            Self::RemoveFeature => ("stale-feature", "A package has a stale feature aspect"),
+            Self::RenameFeature => ("renamed-feature", "A feature aspect has been renamed"),
+            Self::RenameSetting => (
+                "renamed-setting",
+                "A setting refers to a renamed feature aspect",
+            ),
+        }
+    }
+}
+
+impl ChangeReason {
+    fn explain(self) -> &'static str {
+        match self {
+            Self::InScope => "the package is in scope of the feature aspect",
+            Self::Dependency => "it propagates the feature aspect to a dependency",
+            Self::LeafFeature => "it enables a leaf feature",
+            Self::ExtraParam => "it is an extra param of the feature aspect",
+            Self::OptionalDependency => {
+                "whether the param has a `?` must match whether the dependency is optional"
+            }
+            Self::Stale => "the dependency is not in scope of the feature aspect",
+            Self::DefaultFeature => {
+                "the `default-feature` setting of the feature aspect requires it"
+            }
+            Self::Prune => "the package is not in scope of the feature aspect",
+            Self::RemoveAspect => "the feature aspect is being removed",
+            Self::RenameAspect => "the feature aspect is being renamed",
+        }
+    }
+}
+
+impl Change<'_> {
+    /// A human-readable description of the change, for annotations.
+    fn describe(&self) -> String {
+        let Self {
+            package, feature, ..
+        } = self;
+        let param = self.param.unwrap_or_default();
+        let replacement = self.replacement.unwrap_or_default();
+        let description = match self.action {
+            ChangeAction::Add => format!("feature {feature:?} is missing param {param:?}"),
+            ChangeAction::Remove => format!("feature {feature:?} has stale param {param:?}"),
+            ChangeAction::Replace => format!(
+                "param {param:?} of feature {feature:?} should be replaced with {replacement:?}"
+            ),
+            ChangeAction::AddFeature => {
+                format!("package {} is missing feature {feature:?}", package.unwrap_or_default())
+            }
+            ChangeAction::RemoveFeature => {
+                format!(
+                    "feature {feature:?} should be removed from package {}",
+                    package.unwrap_or_default()
+                )
+            }
+            ChangeAction::RenameFeature => {
+                format!("feature {feature:?} should be renamed to {replacement:?}")
+            }
+            ChangeAction::RenameSetting => match self.param {
+                Some(param) => format!(
+                    "setting {param:?} of feature aspect {feature:?} should be replaced with {replacement:?}"
+                ),
+                None => format!(
+                    "settings of feature aspect {feature:?} should refer to it as {replacement:?}"
+                ),
+            },
+        };
+        format!("{description}, since {}", self.reason.explain())
+    }
+
+    /// The manifest path relative to the current directory if possible, which is what CI tools
+    /// expect.
+    fn relative_path(&self) -> String {
+        let path = std::path::Path::new(self.manifest_path);
+        let relative = std::env::current_dir()
+            .ok()
+            .and_then(|dir| Some(path.strip_prefix(dir).ok()?.to_owned()))
+            .unwrap_or_else(|| path.to_owned());
+        relative.to_string_lossy().replace('\\', "/")
+    }
+}
+
+impl Reporter {
+    /// Creates a reporter for the given message format.  With `verify`, changes are annotated as
+    /// errors instead of warnings.
+    pub fn new(format: cli::MessageFormat, verify: bool) -> Self {
+        Self {
+            format,
+            verify,
+            sarif_results: Vec::new(),
+        }
+    }
+
+    /// Reports a change to stdout in the selected message format.
+    pub fn report_change(&mut self, change: &Change) -> anyhow::Result<()> {
+        let level = match (change.applied, self.verify) {
+            (true, _) => "notice",
+            (false, true) => "error",
+            (false, false) => "warning",
+        };
+
+        match self.format {
+            cli::MessageFormat::Human => {}
+            cli::MessageFormat::Json => print_json(change)?,
+            cli::MessageFormat::Github => {
+                let mut properties =
+                    format!("file={}", escape_github_property(&change.relative_path()));
+                if let Some(line) = change.line {
+                    properties.push_str(&format!(",line={line}"));
+                }
+                println!(
+                    "::{level} {properties},title=feature-aspect::{}",
+                    escape_github_message(&change.describe())
+                );
+            }
+            cli::MessageFormat::Sarif => {
+                let mut physical_location = serde_json::json!({
+                    "artifactLocation": { "uri": change.relative_path() },
+                });
+                // A region must have a start, so without a line the whole file is the location
+                if let Some(line) = change.line {
+                    physical_location["region"] = serde_json::json!({ "startLine": line });
+                }
+                let result = serde_json::json!({
+                    "ruleId": change.action.rule().0,
+                    "level": if level == "notice" { "note" } else { level },
+                    "message": { "text": change.describe() },
+                    "locations": [{ "physicalLocation": physical_location }],
+                });
+                self.sarif_results.push(result);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reports the summary of a run to stdout in the selected message format.
+    ///
+    /// For SARIF, this prints the whole log including all results, since it is a single JSON document.
+    pub fn report_summary(&mut self, summary: &Summary) -> anyhow::Result<()> {
+        match self.format {
+            cli::MessageFormat::Human => {}
+            cli::MessageFormat::Json => print_json(summary)?,
+            cli::MessageFormat::Github => {
+                if !summary.success {
+                    println!(
+                    "::error title=feature-aspect::{}",
+                    escape_github_message(
+                        "feature aspects are not up-to-date, run `cargo feature-aspect` to fix them"
+                    )
+                );
+                }
+            }
+            cli::MessageFormat::Sarif => {
+                let actions = [
+                    ChangeAction::Add,
+                    ChangeAction::Remove,
+                    ChangeAction::Replace,
+                    ChangeAction::AddFeature,
+                    ChangeAction::RemoveFeature,
+                    ChangeAction::RenameFeature,
+                    ChangeAction::RenameSetting,
+                ];
+                let rules: Vec<serde_json::Value> = actions
+                    .into_iter()
+                    .map(|action| {
+                        let (id, description) = action.rule();
+                        serde_json::json!({ "id": id, "shortDescription": { "text": description } })
+                    })
+                    .collect();
+                let log = serde_json::json!({
+                    "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                    "version": "2.1.0",
+                    "runs": [{
+                        "tool": {
+                            "driver": {
+                                "name": env!("CARGO_PKG_NAME"),
+                                "version": env!("CARGO_PKG_VERSION"),
+                                "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                                "rules": rules,
+                            },
+                        },
+                        "results": std::mem::take(&mut self.sarif_results),
+                    }],
+                });
+                print_json(&log)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    use std::io::Write as _;
+
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+/// Escapes the message of a GitHub Actions workflow command.
+fn escape_github_message(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property value of a GitHub Actions workflow command.
+fn escape_github_property(value: &str) -> String {
+    escape_github_message(value)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
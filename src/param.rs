@@ -0,0 +1,231 @@
+/// An extra param to add to the aspect feature, as given with `--add-feature-param`, e.g.
+/// `{dep}?/serde if optional({dep}), !target(proc-macro)`.
+///
+/// The param and the arguments of its conditions may contain placeholders, which are only
+/// replaced when expanding the param for a specific package.
+#[derive(Debug)]
+pub struct ExtraParam<'a> {
+    pub template: &'a str,
+    /// All of these conditions need to hold for the param to be added.
+    pub conditions: Vec<Condition<'a>>,
+}
+
+/// A condition on the package that an extra param is added to.
+#[derive(Debug)]
+pub struct Condition<'a> {
+    pub kind: ConditionKind,
+    pub arg: &'a str,
+    pub negated: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConditionKind {
+    /// `dep(x)`: the package has a dependency with the local name `x`.
+    Dep,
+    /// `optional(x)`: the dependency `x` is optional.
+    Optional,
+    /// `required(x)`: the dependency `x` is not optional.
+    Required,
+    /// `feature(f)`: the package has a feature `f`.
+    Feature,
+    /// `target(kind)`: the package has a target of the given kind, e.g. `lib` or `proc-macro`.
+    Target,
+}
+
+impl<'a> ExtraParam<'a> {
+    /// Parses an extra param, with optional conditions after ` if `, separated by commas.
+    ///
+    /// A `dep:x` param always gets an implicit `dep(x)` condition, since cargo rejects it if there
+    /// is no such dependency.
+    pub fn parse(param: &'a str) -> anyhow::Result<Self> {
+        let (template, conditions) = match param.split_once(" if ") {
+            Some((template, conditions)) => (template.trim(), conditions.split(',').collect()),
+            None => (param, Vec::new()),
+        };
+
+        let mut conditions = conditions
+            .into_iter()
+            .map(|c| Condition::parse(c.trim(), param))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if let Some(dep) = template.strip_prefix("dep:") {
+            conditions.push(Condition {
+                kind: ConditionKind::Dep,
+                arg: dep,
+                negated: false,
+            });
+        }
+
+        check_placeholders(template, param)?;
+        for condition in &conditions {
+            check_placeholders(condition.arg, param)?;
+        }
+
+        Ok(Self {
+            template,
+            conditions,
+        })
+    }
+
+    /// Whether the param is expanded once per dependency.
+    pub fn is_per_dependency(&self) -> bool {
+        self.template.contains("{dep}") || self.conditions.iter().any(|c| c.arg.contains("{dep}"))
+    }
+}
+
+impl<'a> Condition<'a> {
+    fn parse(condition: &'a str, param: &str) -> anyhow::Result<Self> {
+        let (negated, condition) = match condition.strip_prefix('!') {
+            Some(condition) => (true, condition.trim_start()),
+            None => (false, condition),
+        };
+
+        let Some((name, arg)) = condition.strip_suffix(')').and_then(|c| c.split_once('(')) else {
+            anyhow::bail!(
+                "invalid condition {condition:?} in feature param {param:?}, expected e.g. `dep(name)`"
+            );
+        };
+
+        let kind = match name.trim() {
+            "dep" => ConditionKind::Dep,
+            "optional" => ConditionKind::Optional,
+            "required" => ConditionKind::Required,
+            "feature" => ConditionKind::Feature,
+            "target" => ConditionKind::Target,
+            other => anyhow::bail!(
+                "unknown condition {other:?} in feature param {param:?}, expected one of `dep`, `optional`, `required`, `feature` or `target`"
+            ),
+        };
+
+        Ok(Self {
+            kind,
+            arg: arg.trim(),
+            negated,
+        })
+    }
+}
+
+/// Replaces the placeholders of a param template or condition argument.
+pub fn expand(template: &str, package: &str, feature: &str, dep: Option<&str>) -> String {
+    let expanded = template
+        .replace("{package}", package)
+        .replace("{feature}", feature);
+    match dep {
+        Some(dep) => expanded.replace("{dep}", dep),
+        None => expanded,
+    }
+}
+
+/// Checks that a part of an extra feature param only uses known placeholders.
+fn check_placeholders(part: &str, param: &str) -> anyhow::Result<()> {
+    let mut rest = part;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            anyhow::bail!("unclosed placeholder in feature param {param:?}");
+        };
+        let placeholder = &rest[start..=start + len];
+        if !matches!(placeholder, "{package}" | "{feature}" | "{dep}") {
+            anyhow::bail!(
+                "unknown placeholder {placeholder} in feature param {param:?}, expected one of {{package}}, {{feature}} or {{dep}}"
+            );
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions<'a>(param: &ExtraParam<'a>) -> Vec<(ConditionKind, &'a str, bool)> {
+        param
+            .conditions
+            .iter()
+            .map(|c| (c.kind, c.arg, c.negated))
+            .collect()
+    }
+
+    #[test]
+    fn without_conditions() {
+        let param = ExtraParam::parse("{dep}?/serde").unwrap();
+        assert_eq!(param.template, "{dep}?/serde");
+        assert!(param.conditions.is_empty());
+        assert!(param.is_per_dependency());
+    }
+
+    #[test]
+    fn conditions_after_if() {
+        let param = ExtraParam::parse("tokio/tracing if required(tokio) , feature(rt)").unwrap();
+        assert_eq!(param.template, "tokio/tracing");
+        assert_eq!(
+            conditions(&param),
+            [
+                (ConditionKind::Required, "tokio", false),
+                (ConditionKind::Feature, "rt", false),
+            ]
+        );
+        assert!(!param.is_per_dependency());
+    }
+
+    #[test]
+    fn negated_conditions() {
+        let param =
+            ExtraParam::parse("{dep}/std if !optional({dep}), ! target(proc-macro)").unwrap();
+        assert_eq!(
+            conditions(&param),
+            [
+                (ConditionKind::Optional, "{dep}", true),
+                (ConditionKind::Target, "proc-macro", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn implicit_dep_condition() {
+        let param = ExtraParam::parse("dep:logging if feature(std)").unwrap();
+        assert_eq!(
+            conditions(&param),
+            [
+                (ConditionKind::Feature, "std", false),
+                (ConditionKind::Dep, "logging", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_conditions() {
+        let err = ExtraParam::parse("x if platform(linux)").unwrap_err();
+        assert!(
+            err.to_string().contains("unknown condition \"platform\""),
+            "{err}"
+        );
+        let err = ExtraParam::parse("x if dep").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid condition \"dep\""),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn invalid_placeholders() {
+        let err = ExtraParam::parse("{crate}/std").unwrap_err();
+        assert!(
+            err.to_string().contains("unknown placeholder {crate}"),
+            "{err}"
+        );
+        let err = ExtraParam::parse("x if dep({dep)").unwrap_err();
+        assert!(err.to_string().contains("unclosed placeholder"), "{err}");
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        assert_eq!(
+            expand("{dep}?/{feature}", "api", "std", Some("storage")),
+            "storage?/std"
+        );
+        // `{dep}` is left alone if there is no dependency to expand it for
+        assert_eq!(expand("{package}-{dep}", "api", "std", None), "api-{dep}");
+    }
+}
@@ -0,0 +1,120 @@
+/// A pattern matching package or feature names in leaf features.
+///
+/// Names are matched exactly, unless they contain the glob wildcards `*` or `?`, or start with
+/// `re:` followed by a regular expression.  Globs and regular expressions have to match the whole
+/// name.
+#[derive(Debug)]
+pub struct Pattern<'a> {
+    source: &'a str,
+    regex: Option<regex::Regex>,
+}
+
+impl<'a> Pattern<'a> {
+    pub fn parse(source: &'a str) -> anyhow::Result<Self> {
+        use anyhow::Context as _;
+
+        let regex = if let Some(regex) = source.strip_prefix("re:") {
+            Some(format!("^(?:{regex})$"))
+        } else if source.contains(['*', '?']) {
+            Some(glob_to_regex(source))
+        } else {
+            None
+        };
+
+        let regex = regex
+            .map(|r| regex::Regex::new(&r))
+            .transpose()
+            .with_context(|| format!("invalid pattern {source:?}"))?;
+
+        Ok(Self { source, regex })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(name),
+            None => self.source == name,
+        }
+    }
+
+    /// The name that this pattern matches, if it only matches a single one.
+    pub fn as_exact(&self) -> Option<&'a str> {
+        self.regex.is_none().then_some(self.source)
+    }
+}
+
+impl PartialEq for Pattern<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut literal = [0; 4];
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut literal))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact() {
+        let pattern = Pattern::parse("std").unwrap();
+        assert_eq!(pattern.as_exact(), Some("std"));
+        assert!(pattern.matches("std"));
+        assert!(!pattern.matches("no-std"));
+    }
+
+    #[test]
+    fn glob() {
+        let pattern = Pattern::parse("tokio-*").unwrap();
+        assert_eq!(pattern.as_exact(), None);
+        assert!(pattern.matches("tokio-util"));
+        assert!(pattern.matches("tokio-"));
+        assert!(!pattern.matches("tokio"));
+        // Globs match the whole name
+        assert!(!pattern.matches("my-tokio-util"));
+
+        let pattern = Pattern::parse("serde?").unwrap();
+        assert!(pattern.matches("serde1"));
+        assert!(!pattern.matches("serde"));
+        assert!(!pattern.matches("serde12"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_syntax() {
+        let pattern = Pattern::parse("a.b+*").unwrap();
+        assert!(pattern.matches("a.b+c"));
+        assert!(!pattern.matches("axb+c"));
+        assert!(!pattern.matches("a.bbc"));
+    }
+
+    #[test]
+    fn regex_is_anchored() {
+        let pattern = Pattern::parse("re:std|alloc").unwrap();
+        assert_eq!(pattern.as_exact(), None);
+        assert!(pattern.matches("std"));
+        assert!(pattern.matches("alloc"));
+        // Without the group around the regex, `^std|alloc$` would match these
+        assert!(!pattern.matches("std-alloc"));
+        assert!(!pattern.matches("no-alloc"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let err = Pattern::parse("re:(std").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid pattern \"re:(std\""),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{cli, context, metadata, output};
+use std::{cmp, collections};
+
+/// A feature that looks like a hand-written feature aspect, since it exists in several workspace
+/// members and is forwarded to dependencies with the same name.
+struct Candidate<'a> {
+    feature: &'a str,
+    /// The workspace members that have the feature.
+    packages: Vec<&'a cargo_metadata::Package>,
+    /// The members with the feature that don't depend on any other member with the feature.
+    leaves: Vec<&'a cargo_metadata::Package>,
+    /// The number of members that would be in scope with the leaves as leaf features.
+    in_scope: usize,
+    /// Descriptions of where the propagation chain is broken, at most one per package.
+    gaps: Vec<String>,
+}
+
+/// Scans the features of all workspace members for existing propagation chains, and reports
+/// them as feature aspect candidates.
+///
+/// The report goes to stderr like all other output, while the suggested
+/// `[workspace.metadata.feature-aspect]` entries are printed to stdout.
+pub fn suggest_aspects(ws: &metadata::Workspace) -> anyhow::Result<()> {
+    let mut features: collections::BTreeMap<&str, Vec<&cargo_metadata::Package>> =
+        collections::BTreeMap::new();
+    for package in &ws.members {
+        for feature in package.features.keys() {
+            // Every crate might have a default feature, which is not an aspect
+            if feature != "default" {
+                features.entry(feature).or_default().push(package);
+            }
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for (feature, packages) in features {
+        if packages.len() > 1 {
+            candidates.extend(find_candidate(ws, feature, packages)?);
+        }
+    }
+    candidates.sort_by_key(|c| cmp::Reverse(c.packages.len()));
+
+    if candidates.is_empty() {
+        return output::shell_status("Finished", "no feature propagation chains found");
+    }
+
+    for candidate in &candidates {
+        report_candidate(candidate)?;
+    }
+
+    for (idx, candidate) in candidates.iter().enumerate() {
+        if idx > 0 {
+            println!();
+        }
+        let leaf_features: toml_edit::Array = candidate
+            .leaves
+            .iter()
+            .map(|p| format!("{}/{}", p.name, candidate.feature))
+            .collect();
+        println!(
+            "[workspace.metadata.feature-aspect.{}]",
+            toml_edit::Key::new(candidate.feature)
+        );
+        println!("leaf-features = {leaf_features}");
+    }
+
+    Ok(())
+}
+
+fn find_candidate<'a>(
+    ws: &'a metadata::Workspace,
+    feature: &'a str,
+    packages: Vec<&'a cargo_metadata::Package>,
+) -> anyhow::Result<Option<Candidate<'a>>> {
+    let has_feature = |p: &cargo_metadata::Package| p.features.contains_key(feature);
+    let forwards = |p: &cargo_metadata::Package, dep_name: &str| {
+        p.features.get(feature).is_some_and(|params| {
+            params.iter().any(|param| {
+                param.split_once('/').is_some_and(|(dep, dep_feature)| {
+                    dep.strip_suffix('?').unwrap_or(dep) == dep_name && dep_feature == feature
+                })
+            })
+        })
+    };
+
+    // Dependencies are filtered like for an upward feature aspect with default settings, which
+    // doesn't depend on the leaf features
+    let dependency_ctx =
+        context::Context::from_leaf_features(feature, &[], cli::Direction::Upward)?;
+    let member_dependencies = |p: &'a cargo_metadata::Package| {
+        dependency_ctx.dependencies(ws, p).filter_map(|dep| {
+            Some((
+                metadata::local_name(dep),
+                ws.member(ws.resolve_member(dep)?)?,
+            ))
+        })
+    };
+
+    // Features that exist in several crates by coincidence, e.g. `std`, aren't interesting unless
+    // they are actually forwarded somewhere
+    let is_forwarded = packages.iter().any(|p| {
+        member_dependencies(p).any(|(dep_name, dep)| has_feature(dep) && forwards(p, dep_name))
+    });
+    if !is_forwarded {
+        return Ok(None);
+    }
+
+    let leaves: Vec<&cargo_metadata::Package> = packages
+        .iter()
+        .copied()
+        .filter(|p| !member_dependencies(p).any(|(_, dep)| has_feature(dep)))
+        .collect();
+    if leaves.is_empty() {
+        return Ok(None);
+    }
+
+    // The scope of the suggested feature aspect, computed exactly like a real run would
+    let leaf_features: Vec<String> = leaves
+        .iter()
+        .map(|p| format!("{}/{feature}", p.name))
+        .collect();
+    let mut ctx =
+        context::Context::from_leaf_features(feature, &leaf_features, cli::Direction::Upward)?;
+    crate::compute_scope(ws, &mut ctx)?;
+    let in_scope: Vec<&cargo_metadata::Package> = ws
+        .members
+        .iter()
+        .filter(|p| ctx.in_scope_packages.contains(&p.id))
+        .collect();
+
+    let mut gaps = Vec::new();
+    for &package in &in_scope {
+        let mut dep_names: Vec<&str> = ctx
+            .dependencies(ws, package)
+            .filter(|dep| ctx.in_scope_dependency(ws, package, dep).is_some())
+            .map(metadata::local_name)
+            .collect();
+        dep_names.sort_unstable();
+        dep_names.dedup();
+
+        if !has_feature(package) {
+            gaps.push(format!(
+                "package {} has no feature {feature:?}, but depends on {}",
+                package.name,
+                dep_names.join(", ")
+            ));
+            continue;
+        }
+
+        let missing: Vec<&str> = dep_names
+            .into_iter()
+            .filter(|dep_name| !forwards(package, dep_name))
+            .collect();
+        if !missing.is_empty() {
+            gaps.push(format!(
+                "package {} does not forward feature {feature:?} to {}",
+                package.name,
+                missing.join(", ")
+            ));
+        }
+    }
+
+    Ok(Some(Candidate {
+        feature,
+        packages,
+        leaves,
+        in_scope: in_scope.len(),
+        gaps,
+    }))
+}
+
+fn report_candidate(candidate: &Candidate) -> anyhow::Result<()> {
+    let feature = candidate.feature;
+    let leaf_names: Vec<&str> = candidate.leaves.iter().map(|p| p.name.as_str()).collect();
+    let leaf_flags: Vec<String> = candidate
+        .leaves
+        .iter()
+        .map(|p| format!("--leaf-feature {}/{feature}", p.name))
+        .collect();
+
+    output::shell_status(
+        "Found",
+        &format!(
+            "feature {feature:?} in {} workspace members",
+            candidate.packages.len()
+        ),
+    )?;
+    output::shell_status("Leaves", &leaf_names.join(", "))?;
+    output::shell_status(
+        "Coverage",
+        &format!(
+            "{} of {} crates in scope propagate the feature",
+            candidate.in_scope - candidate.gaps.len(),
+            candidate.in_scope
+        ),
+    )?;
+    for gap in &candidate.gaps {
+        output::shell_print("Gap", gap, termcolor::Color::Yellow, true)?;
+    }
+    output::shell_status(
+        "Reproduce",
+        &format!("cargo feature-aspect {}", leaf_flags.join(" ")),
+    )?;
+
+    Ok(())
+}
@@ -1,34 +1,117 @@
+use crate::{context, metadata, output};
 use std::{collections, fmt};
 
 // utility for logging topo_sort results lazily
 struct TopoNodes<'a, V>(topo_sort::SortResults<(&'a V, &'a collections::HashSet<V>)>);
 
-pub fn sort_packages(packages: &mut [cargo_metadata::Package]) -> anyhow::Result<()> {
-    const DEP_CYCLE_ERR: &str = "Dependency cycle detected! Resolve this using other cargo commands first (e.g. `cargo build` should fail with a decent error message).";
+/// Sorts the workspace members topologically, so that dependencies come before their dependees,
+/// and returns the resulting order as indices into `ws.members`.
+///
+/// Only dependencies that propagate the feature aspect are considered, so e.g. the common pattern
+/// of two crates dev-depending on each other does not count as a cycle.  If there still is a
+/// cycle, a warning naming its members is printed, and the members that could not be sorted are
+/// put last in their original order.  Callers must then compute the scope to a fixed point instead
+/// of relying on a single pass in this order.
+pub fn sort_packages(
+    ws: &metadata::Workspace,
+    ctx: &context::Context,
+) -> anyhow::Result<Vec<usize>> {
+    let indices: collections::HashMap<&cargo_metadata::PackageId, usize> = ws
+        .members
+        .iter()
+        .enumerate()
+        .map(|(idx, p)| (&p.id, idx))
+        .collect();
 
-    let mut topo = topo_sort::TopoSort::<&str>::with_capacity(packages.len());
-    for package in packages.iter() {
+    let mut topo =
+        topo_sort::TopoSort::<&cargo_metadata::PackageId>::with_capacity(ws.members.len());
+    for package in &ws.members {
         topo.insert(
-            package.name.as_str(),
-            package.dependencies.iter().map(|d| d.name.as_str()),
+            &package.id,
+            ctx.dependencies(ws, package)
+                .filter_map(|d| ws.resolve_member(d)),
         );
     }
 
     tracing::debug!(nodes=?TopoNodes(topo.to_vec()), "topo sorted nodes");
 
-    // Contains a mapping of package name to topological sort index
-    let order: collections::HashMap<String, usize> = topo
+    let mut order: Vec<usize> = topo
         .nodes()
-        .enumerate()
-        .map(|(idx, node_result)| Ok(((*node_result?).to_string(), idx)))
-        .collect::<anyhow::Result<_>>()
-        .map_err(|_| anyhow::anyhow!(DEP_CYCLE_ERR))?;
+        .map_while(Result::ok)
+        .map(|id| indices[id])
+        .collect();
+
+    if order.len() < ws.members.len() {
+        let sorted: collections::HashSet<usize> = order.iter().copied().collect();
+        let unsorted: Vec<usize> = (0..ws.members.len())
+            .filter(|idx| !sorted.contains(idx))
+            .collect();
+
+        for cycle in find_cycles(ws, ctx, &indices, &unsorted) {
+            let names: Vec<&str> = cycle
+                .iter()
+                .map(|&idx| ws.members[idx].name.as_str())
+                .collect();
+            output::shell_warn(&format!(
+                "dependency cycle between workspace members {}; the feature aspect scope will be computed iteratively",
+                names.join(", ")
+            ))?;
+        }
+
+        order.extend(unsorted);
+    }
+
+    tracing::debug!(packages=?(order.iter().map(|&idx| ws.members[idx].name.as_str()).collect::<Vec<_>>()), "topo sorted package order");
 
-    packages.sort_by_key(|p| order[p.name.as_str()]);
+    Ok(order)
+}
+
+/// Finds the groups of packages among `unsorted` that actually are part of a cycle, as opposed to
+/// packages that merely depend on a cycle.
+fn find_cycles(
+    ws: &metadata::Workspace,
+    ctx: &context::Context,
+    indices: &collections::HashMap<&cargo_metadata::PackageId, usize>,
+    unsorted: &[usize],
+) -> Vec<Vec<usize>> {
+    // The set of unsorted packages is usually tiny, so we just compute the full reachability of
+    // each of them to find the strongly connected components.
+    let reachable_from = |start: usize| {
+        let mut seen = collections::BTreeSet::new();
+        let mut queue = vec![start];
+        while let Some(idx) = queue.pop() {
+            for dep in ctx.dependencies(ws, &ws.members[idx]) {
+                if let Some(&dep_idx) = ws.resolve_member(dep).and_then(|id| indices.get(id)) {
+                    if unsorted.contains(&dep_idx) && seen.insert(dep_idx) {
+                        queue.push(dep_idx);
+                    }
+                }
+            }
+        }
+        seen
+    };
+
+    let reachable: collections::HashMap<usize, collections::BTreeSet<usize>> = unsorted
+        .iter()
+        .map(|&idx| (idx, reachable_from(idx)))
+        .collect();
 
-    tracing::debug!(packages=?(packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>()), "topo sorted package order");
+    let mut cycles: Vec<Vec<usize>> = Vec::new();
+    for &idx in unsorted {
+        if !reachable[&idx].contains(&idx) || cycles.iter().any(|c| c.contains(&idx)) {
+            continue;
+        }
+
+        cycles.push(
+            reachable[&idx]
+                .iter()
+                .copied()
+                .filter(|other| reachable[other].contains(&idx))
+                .collect(),
+        );
+    }
 
-    Ok(())
+    cycles
 }
 
 impl<'a, V> fmt::Debug for TopoNodes<'a, V>
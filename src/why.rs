@@ -0,0 +1,147 @@
+use crate::{cli, context, metadata, output};
+use std::collections;
+
+/// Prints why a workspace member is in scope for a feature aspect, once the scope is computed.
+pub fn explain<'w>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context<'w>,
+    package: &'w cargo_metadata::Package,
+) -> anyhow::Result<()> {
+    let feature = ctx.feature_name.as_ref();
+    let pkg_name = &package.name;
+
+    if !ctx.in_scope_packages.contains(&package.id) {
+        let reason = if ctx.is_stopped(ws, package) {
+            "it is a stop point"
+        } else {
+            match ctx.direction {
+                cli::Direction::Upward => "it doesn't depend on any package with a leaf feature",
+                cli::Direction::Downward => "no package with a leaf feature depends on it",
+            }
+        };
+        return output::shell_status(
+            "Unaffected",
+            &format!(
+                "package {pkg_name} is not in scope for feature aspect {feature:?}, since {reason}"
+            ),
+        );
+    }
+
+    let path = match ctx.direction {
+        cli::Direction::Upward => shortest_path(ws, ctx, &package.id, |id| {
+            in_scope_dependencies(ws, ctx, id)
+        }),
+        cli::Direction::Downward => {
+            let mut path =
+                shortest_path(ws, ctx, &package.id, |id| in_scope_dependees(ws, ctx, id));
+            path.reverse();
+            path
+        }
+    };
+
+    let path: Vec<String> = path
+        .into_iter()
+        .map(|id| {
+            let (name, leaf_features) = describe_node(ws, ctx, id);
+            if leaf_features.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{name}[{}]", leaf_features.join(","))
+            }
+        })
+        .collect();
+    output::shell_status("Path", &path.join(" -> "))?;
+
+    // Show what each in-scope dependency contributes to the aspect feature, as it would be generated
+    let feature = ctx.local_feature_name(ws, &package.id);
+    for (dep_name, _, params) in crate::dependency_params(ctx, ws, package) {
+        output::shell_status(
+            "Dependency",
+            &format!("{dep_name} contributes {params:?} to feature {feature:?}"),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Finds the shortest path from an in-scope package to a package with leaf features, using a
+/// breadth-first search along the given edges.
+fn shortest_path<'w, F>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context<'w>,
+    start: &'w cargo_metadata::PackageId,
+    neighbors: F,
+) -> Vec<&'w cargo_metadata::PackageId>
+where
+    F: Fn(&'w cargo_metadata::PackageId) -> Vec<&'w cargo_metadata::PackageId>,
+{
+    let mut predecessors = collections::HashMap::new();
+    let mut queue = collections::VecDeque::from([start]);
+    predecessors.insert(start, None);
+
+    while let Some(id) = queue.pop_front() {
+        if !describe_node(ws, ctx, id).1.is_empty() {
+            let mut path = vec![id];
+            while let Some(&Some(prev)) = predecessors.get(path[path.len() - 1]) {
+                path.push(prev);
+            }
+            path.reverse();
+            return path;
+        }
+
+        for next in neighbors(id) {
+            if !predecessors.contains_key(next) {
+                predecessors.insert(next, Some(id));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    // Every in-scope package is connected to a leaf, but don't make a fuss if it isn't
+    vec![start]
+}
+
+/// The name and matching leaf features of a workspace member or external package.
+pub fn describe_node<'w>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context<'w>,
+    id: &'w cargo_metadata::PackageId,
+) -> (&'w str, Vec<&'w str>) {
+    if let Some(package) = ws.member(id) {
+        (&package.name, ctx.leaf_features(package))
+    } else if let Some(package) = ws.external(id) {
+        let leaf_features = ctx.external_leaf_features.get(id).cloned();
+        (&package.name, leaf_features.unwrap_or_default())
+    } else {
+        (&id.repr, Vec::new())
+    }
+}
+
+fn in_scope_dependencies<'w>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context,
+    id: &cargo_metadata::PackageId,
+) -> Vec<&'w cargo_metadata::PackageId> {
+    let Some(package) = ws.member(id) else {
+        return Vec::new();
+    };
+    ctx.dependencies(ws, package)
+        .filter_map(|dep| ctx.in_scope_dependency(ws, package, dep))
+        .collect()
+}
+
+fn in_scope_dependees<'w>(
+    ws: &'w metadata::Workspace,
+    ctx: &context::Context,
+    id: &cargo_metadata::PackageId,
+) -> Vec<&'w cargo_metadata::PackageId> {
+    ws.members
+        .iter()
+        .filter(|p| ctx.in_scope_packages.contains(&p.id))
+        .filter(|p| {
+            ctx.dependencies(ws, p)
+                .any(|dep| ws.resolve_member(dep) == Some(id))
+        })
+        .map(|p| &p.id)
+        .collect()
+}
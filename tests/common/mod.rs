@@ -0,0 +1,68 @@
+//! Helpers for running `cargo feature-aspect` in fixture workspaces.
+
+// Each test binary only uses some of the helpers
+#![allow(dead_code)]
+
+use std::{fs, path, process};
+
+/// Copies a fixture workspace to a fresh temporary directory, so that runs can't affect each
+/// other or the fixture.
+pub fn fixture(name: &str, test: &str) -> path::PathBuf {
+    fn copy_dir(from: &path::Path, to: &path::Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &target);
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    let source = path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let dir = std::env::temp_dir().join(format!("cargo-feature-aspect-{}-{test}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    copy_dir(&source, &dir);
+    dir
+}
+
+/// Runs `cargo feature-aspect` in a fixture workspace, returning its stderr.
+pub fn run(dir: &path::Path, args: &[&str]) -> String {
+    let output = run_output(dir, args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    stderr
+}
+
+/// Runs `cargo feature-aspect` in a fixture workspace that is expected to fail, returning its
+/// stderr.
+pub fn run_failing(dir: &path::Path, args: &[&str]) -> String {
+    let output = run_output(dir, args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success(), "{stderr}");
+    stderr
+}
+
+/// Runs `cargo feature-aspect` in a fixture workspace, returning its stdout, which is where
+/// machine-readable messages go.  The run may fail, e.g. because of `--verify`.
+pub fn run_stdout(dir: &path::Path, args: &[&str]) -> String {
+    String::from_utf8(run_output(dir, args).stdout).unwrap()
+}
+
+fn run_output(dir: &path::Path, args: &[&str]) -> process::Output {
+    process::Command::new(env!("CARGO_BIN_EXE_cargo-feature-aspect"))
+        .arg("feature-aspect")
+        .args(args)
+        .arg("--offline")
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        // Paths in annotations are relative to the current directory
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
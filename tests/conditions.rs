@@ -0,0 +1,29 @@
+//! Conditions of extra feature params look at all dependencies of a package, even the ones that
+//! don't propagate the feature aspect.
+
+mod common;
+
+use common::{fixture, run};
+use std::fs;
+
+#[test]
+fn dependency_conditions_include_stopped_dependencies() {
+    let dir = fixture("external-forwards", "stopped-condition");
+    let stderr = run(
+        &dir,
+        &[
+            "-f",
+            "logging/enable-tracing",
+            "--stop-at",
+            "api:ext",
+            "--add-feature-param",
+            "ext/std if required(ext)",
+            "--dry-run",
+        ],
+    );
+    assert!(
+        stderr.contains(r#"Would add "ext/std" to package api feature "enable-tracing""#),
+        "{stderr}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
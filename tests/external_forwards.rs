@@ -0,0 +1,60 @@
+//! Params that forward a feature to crates outside of the workspace, like `ext/std`, are not
+//! managed by us unless they forward a leaf feature, so they must never be removed.
+
+mod common;
+
+use common::{fixture, run};
+use std::fs;
+
+#[test]
+fn upward_keeps_external_forwards() {
+    let dir = fixture("external-forwards", "upward");
+    let stderr = run(
+        &dir,
+        &["-n", "std", "-f", "logging/enable-tracing", "--dry-run"],
+    );
+    assert!(stderr.contains(r#"Would add "storage/std" to package api feature "std""#));
+    assert!(!stderr.contains("ext/std"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn downward_keeps_external_forwards() {
+    let dir = fixture("external-forwards", "downward");
+    let stderr = run(
+        &dir,
+        &["-f", "api/std", "--direction", "downward", "--dry-run"],
+    );
+    assert!(stderr.contains(r#"Would add "storage/std" to package api feature "std""#));
+    assert!(stderr.contains(r#"Would add feature "std" to package logging"#));
+    assert!(!stderr.contains("ext/std"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn downward_prune_removes_generated_features() {
+    let dir = fixture("external-forwards", "downward-prune");
+    run(&dir, &["-f", "api/std", "--direction", "downward"]);
+
+    // Nothing is in scope anymore, since no crate has the leaf feature
+    let stderr = run(
+        &dir,
+        &[
+            "-n",
+            "std",
+            "-f",
+            "api/no-std",
+            "--direction",
+            "downward",
+            "--prune",
+            "--dry-run",
+        ],
+    );
+    assert!(stderr.contains(r#"Would remove feature "std" from package logging"#));
+    assert!(stderr.contains(r#"Would remove feature "std" from package storage"#));
+    assert!(
+        stderr.contains(r#"not pruning feature "std" from package api"#),
+        "{stderr}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
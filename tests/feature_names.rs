@@ -0,0 +1,93 @@
+//! Packages can override the name of an aspect feature in `[package.metadata.feature-aspect]
+//! feature-names`, which removing and renaming the feature aspect must respect.
+
+mod common;
+
+use common::{fixture, run};
+use std::fs;
+
+#[test]
+fn remove_uses_local_feature_names() {
+    let dir = fixture("feature-names", "remove");
+    run(
+        &dir,
+        &[
+            "remove",
+            "-n",
+            "enable-tracing",
+            "-f",
+            "logging/enable-tracing",
+        ],
+    );
+
+    assert_eq!(
+        fs::read_to_string(dir.join("storage/Cargo.toml")).unwrap(),
+        r#"[package]
+name = "storage"
+version = "0.1.0"
+edition = "2021"
+
+[package.metadata.feature-aspect]
+feature-names = { enable-tracing = "trace" }
+
+[dependencies]
+logging = { path = "../logging" }
+
+[features]
+default = []
+"#
+    );
+    assert!(!fs::read_to_string(dir.join("api/Cargo.toml"))
+        .unwrap()
+        .contains("[features]"));
+    // The leaf feature is kept
+    assert!(fs::read_to_string(dir.join("logging/Cargo.toml"))
+        .unwrap()
+        .contains("enable-tracing = []"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rename_keeps_local_feature_names() {
+    let dir = fixture("feature-names", "rename");
+    run(
+        &dir,
+        &["rename", "--from", "enable-tracing", "--to", "tracing"],
+    );
+
+    assert_eq!(
+        fs::read_to_string(dir.join("storage/Cargo.toml")).unwrap(),
+        r#"[package]
+name = "storage"
+version = "0.1.0"
+edition = "2021"
+
+[package.metadata.feature-aspect]
+feature-names = { tracing = "trace" }
+
+[dependencies]
+logging = { path = "../logging" }
+
+[features]
+default = ["trace"]
+trace = ["logging/tracing"]
+"#
+    );
+    assert!(fs::read_to_string(dir.join("api/Cargo.toml"))
+        .unwrap()
+        .contains(r#"tracing = ["dep:storage", "storage?/trace"]"#));
+
+    // The renamed feature aspect is up-to-date, including the override
+    let stderr = run(
+        &dir,
+        &[
+            "-f",
+            "logging/tracing",
+            "--add-feature-param",
+            "dep:storage",
+            "--verify",
+        ],
+    );
+    assert!(!stderr.contains("Would"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,117 @@
+//! Changes are reported on stdout in the selected `--message-format`, one record per change and a
+//! summary at the end.
+
+mod common;
+
+use common::{fixture, run_stdout};
+use std::fs;
+
+#[test]
+fn json_reports_changes_and_summary() {
+    let dir = fixture("external-forwards", "json");
+    let stdout = run_stdout(
+        &dir,
+        &[
+            "-f",
+            "logging/enable-tracing",
+            "--message-format",
+            "json",
+            "--dry-run",
+        ],
+    );
+    let records: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(records.len(), 3, "{stdout}");
+    assert_eq!(records[0]["type"], "change");
+    assert_eq!(records[0]["package"], "api");
+    assert_eq!(records[0]["line"], 10);
+    assert_eq!(records[0]["feature"], "enable-tracing");
+    assert_eq!(records[0]["param"], "storage/enable-tracing");
+    assert_eq!(records[0]["action"], "add");
+    assert_eq!(records[0]["reason"], "dependency");
+    assert_eq!(records[0]["applied"], false);
+    assert_eq!(records[1]["package"], "storage");
+    assert_eq!(records[1]["param"], "logging/enable-tracing");
+    assert_eq!(
+        records[2],
+        serde_json::json!({
+            "type": "summary",
+            "changed_packages": ["api", "storage"],
+            "dry_run": true,
+            "verify": false,
+            "success": true,
+        })
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn github_annotates_manifest_lines() {
+    let dir = fixture("external-forwards", "github");
+    let stdout = run_stdout(
+        &dir,
+        &[
+            "-f",
+            "logging/enable-tracing",
+            "--message-format",
+            "github",
+            "--verify",
+        ],
+    );
+
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            r#"::error file=api/Cargo.toml,line=10,title=feature-aspect::feature "enable-tracing" is missing param "storage/enable-tracing", since it propagates the feature aspect to a dependency"#,
+            // There is no `[features]` table to point at yet
+            r#"::error file=storage/Cargo.toml,title=feature-aspect::feature "enable-tracing" is missing param "logging/enable-tracing", since it propagates the feature aspect to a dependency"#,
+            "::error title=feature-aspect::feature aspects are not up-to-date, run `cargo feature-aspect` to fix them",
+        ]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn sarif_reports_a_single_log() {
+    let dir = fixture("external-forwards", "sarif");
+    let stdout = run_stdout(
+        &dir,
+        &[
+            "-f",
+            "logging/enable-tracing",
+            "--message-format",
+            "sarif",
+            "--dry-run",
+        ],
+    );
+    let log: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    assert_eq!(log["version"], "2.1.0");
+    let run = &log["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "cargo-feature-aspect");
+    let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+    assert!(rules.iter().any(|r| r["id"] == "missing-param"));
+
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2, "{stdout}");
+    for result in results {
+        assert_eq!(result["ruleId"], "missing-param");
+        assert_eq!(result["level"], "warning");
+    }
+    assert_eq!(
+        results[0]["locations"][0]["physicalLocation"],
+        serde_json::json!({
+            "artifactLocation": { "uri": "api/Cargo.toml" },
+            "region": { "startLine": 10 },
+        })
+    );
+    // An empty region is invalid, so a change without a line has none
+    assert_eq!(
+        results[1]["locations"][0]["physicalLocation"],
+        serde_json::json!({ "artifactLocation": { "uri": "storage/Cargo.toml" } })
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
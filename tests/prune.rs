@@ -0,0 +1,36 @@
+//! With `--prune`, the aspect feature is removed from packages that are no longer in scope, but
+//! only if that can't break anything that was written by hand.
+
+mod common;
+
+use common::{fixture, run};
+use std::fs;
+
+#[test]
+fn upward_prune_removes_only_generated_features() {
+    let dir = fixture("prune", "upward");
+    // The leaf feature was renamed, so nothing is in scope of the feature aspect anymore
+    let stderr = run(
+        &dir,
+        &[
+            "-n",
+            "enable-tracing",
+            "-f",
+            "logging/log-spans",
+            "--prune",
+            "--dry-run",
+        ],
+    );
+
+    assert!(stderr.contains(r#"Would remove feature "enable-tracing" from package storage"#));
+    assert!(stderr.contains(
+        r#"not pruning feature "enable-tracing" from package cache since it has params not generated by this tool: ["verbose"]"#
+    ));
+    assert!(stderr.contains(
+        r#"not pruning feature "enable-tracing" from package api since it is enabled by features ["full"]"#
+    ));
+    // Empty features are only generated by downward feature aspects
+    assert!(!stderr.contains("package logging"), "{stderr}");
+    assert_eq!(stderr.matches("Would remove").count(), 1, "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,53 @@
+//! Removing a feature aspect keeps the leaf features, since code is gated on them.
+
+mod common;
+
+use common::{fixture, run, run_failing};
+use std::fs;
+
+#[test]
+fn remove_keeps_leaf_features() {
+    let dir = fixture("external-forwards", "remove");
+    run(&dir, &["-f", "logging/enable-tracing"]);
+
+    let stderr = run(
+        &dir,
+        &[
+            "remove",
+            "-n",
+            "enable-tracing",
+            "-f",
+            "logging/enable-tracing",
+            "--dry-run",
+        ],
+    );
+    assert!(stderr.contains(r#"Would remove feature "enable-tracing" from package api"#));
+    assert!(stderr.contains(r#"Would remove feature "enable-tracing" from package storage"#));
+    assert!(!stderr.contains("package logging"), "{stderr}");
+
+    let stderr = run(
+        &dir,
+        &[
+            "remove",
+            "-n",
+            "enable-tracing",
+            "--remove-leaf-features",
+            "--dry-run",
+        ],
+    );
+    assert!(stderr.contains(r#"Would remove feature "enable-tracing" from package logging"#));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn remove_requires_leaf_features() {
+    let dir = fixture("external-forwards", "remove-unknown-leaves");
+    run(&dir, &["-f", "logging/enable-tracing"]);
+
+    // Leaf features can't be told apart from aspect features that only forward to external crates
+    // or only have extra params, so they are not guessed
+    let stderr = run_failing(&dir, &["remove", "-n", "enable-tracing", "--dry-run"]);
+    assert!(stderr.contains("--leaf-feature"), "{stderr}");
+    assert!(!stderr.contains("Would remove"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,83 @@
+//! Renaming a feature aspect keeps the position and formatting of everything it touches, and
+//! renames its settings in the workspace metadata too, so that it isn't recreated under the old
+//! name.
+
+mod common;
+
+use common::{fixture, run};
+use std::fs;
+
+#[test]
+fn rename_keeps_formatting_and_renames_settings() {
+    let dir = fixture("rename", "rename");
+    run(
+        &dir,
+        &[
+            "rename",
+            "--from",
+            "enable-tracing",
+            "--to",
+            "tracing",
+            "--alias",
+        ],
+    );
+
+    assert_eq!(
+        fs::read_to_string(dir.join("Cargo.toml")).unwrap(),
+        r#"[workspace]
+members = ["api", "storage", "logging"]
+resolver = "2"
+
+# Spans are emitted by the `logging` crate
+[workspace.metadata.feature-aspect.tracing]
+leaf-features = [
+    "logging/tracing", # the subscriber
+]
+"#
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("logging/Cargo.toml")).unwrap(),
+        r#"[package]
+name = "logging"
+version = "0.1.0"
+edition = "2021"
+
+[features]
+default = ["std"]
+# Emits spans
+tracing = [] # no dependencies
+std = []
+enable-tracing = ["tracing"]
+"#
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("storage/Cargo.toml")).unwrap(),
+        r#"[package]
+name = "storage"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+logging = { path = "../logging" }
+
+[features]
+tracing = [
+    "logging/tracing", # forwarded
+]
+enable-tracing = ["tracing"]
+"#
+    );
+    // Packages that can't be published don't need an alias
+    assert!(fs::read_to_string(dir.join("api/Cargo.toml"))
+        .unwrap()
+        .ends_with("[features]\nfull = [\"tracing\"]\ntracing = [\"storage/tracing\"]\n"));
+
+    // The declared feature aspect is up-to-date under its new name
+    let stderr = run(&dir, &["--verify"]);
+    assert!(
+        stderr.contains(r#"feature aspect for feature "tracing""#),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("Would"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
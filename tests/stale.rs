@@ -0,0 +1,49 @@
+//! Params that forward the feature aspect to a dependency that is no longer in scope are removed,
+//! but those of dependencies that were removed altogether can't be, since cargo can't load the
+//! workspace.
+
+mod common;
+
+use common::{fixture, run, run_failing};
+use std::fs;
+
+#[test]
+fn removes_params_of_dependencies_that_left_scope() {
+    let dir = fixture("prune", "left-scope");
+    let stderr = run(
+        &dir,
+        &[
+            "-n",
+            "enable-tracing",
+            "-f",
+            "logging/enable-tracing",
+            "--stop-at",
+            "api:cache",
+            "--dry-run",
+        ],
+    );
+
+    assert!(stderr.contains(
+        r#"Would remove "cache/enable-tracing" from package api feature "enable-tracing""#
+    ));
+    assert_eq!(stderr.matches("Would").count(), 1, "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn explains_params_of_removed_dependencies() {
+    let dir = fixture("prune", "removed-dependency");
+    let manifest_path = dir.join("storage").join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let manifest = manifest.replace("[dependencies]\nlogging = { path = \"../logging\" }\n", "");
+    fs::write(&manifest_path, manifest).unwrap();
+
+    let stderr = run_failing(
+        &dir,
+        &["-n", "enable-tracing", "-f", "logging/enable-tracing"],
+    );
+
+    assert!(stderr.contains("remove the param by hand"), "{stderr}");
+    assert!(stderr.contains("`logging` is not a dependency"), "{stderr}");
+    fs::remove_dir_all(dir).unwrap();
+}